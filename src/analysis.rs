@@ -0,0 +1,139 @@
+use anyhow::anyhow;
+use super::vm::Instruction;
+use super::cfg::{Cfg, Target};
+
+// The result of running a program, worked out from its control-flow graph.
+// acc is exact, as if the accumulator never overflowed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Analysis {
+    Terminates {
+        acc: i128,
+        steps: u64,
+    },
+    JumpsOutOfRange {
+        ip: i32,
+        acc: i128,
+        steps: u64,
+    },
+    // The program reaches `ip` a second time after `steps` steps, with `acc`
+    // as it was at that point, and then repeats `body` forever.
+    NeverTerminates {
+        ip: i32,
+        acc: i128,
+        steps: u64,
+        body: Vec<usize>,
+    },
+}
+
+impl Analysis {
+    pub fn terminates(&self) -> bool {
+        matches!(self, Analysis::Terminates { .. })
+    }
+}
+
+// Without conditional branches every block has a single successor, so
+// execution follows one path through the CFG: it either leaves the program or
+// enters a loop, which it can never leave. Each block's effect on acc is a
+// constant, so the whole run can be summarised one block at a time.
+pub fn analyse(instructions: &[Instruction]) -> anyhow::Result<Analysis> {
+    if let Some(inst) = instructions.iter().find(|i| !matches!(i, Instruction::Acc(_) | Instruction::Jmp(_) | Instruction::Nop(_))) {
+        Err(anyhow!("cannot analyse `{}`, only acc, jmp and nop are supported", inst))?;
+    }
+
+    let cfg = Cfg::new(instructions);
+    let mut acc = 0i128;
+    let mut steps = 0u64;
+    let mut visited = vec![false; cfg.blocks().len()];
+    let mut block = match cfg.entry() {
+        Some(entry) => entry,
+        None => return Ok(Analysis::Terminates { acc, steps }),
+    };
+
+    loop {
+        if visited[block] {
+            let body = cfg.loops()
+                .into_iter()
+                .find(|l| l.contains(&block))
+                .expect("a revisited block is part of a loop");
+            return Ok(Analysis::NeverTerminates {
+                ip: cfg.blocks()[block].start as i32,
+                acc,
+                steps,
+                body,
+            });
+        }
+        visited[block] = true;
+
+        let b = &cfg.blocks()[block];
+        for inst in instructions[b.start..b.end].iter() {
+            if let Instruction::Acc(a) = inst {
+                acc += *a as i128;
+            }
+        }
+        steps += b.len() as u64;
+
+        block = match b.successors[..] {
+            [Target::Block(next)] => next,
+            [Target::Exit] => return Ok(Analysis::Terminates { acc, steps }),
+            [Target::OutOfRange(ip)] => return Ok(Analysis::JumpsOutOfRange { ip, acc, steps }),
+            _ => unreachable!("blocks without conditional branches have one successor"),
+        };
+    }
+}
+
+#[test]
+fn test_analyse() {
+    use Instruction::*;
+
+    let day8 = super::asm::parse_asm(include_str!("bin/day8part1.txt")).unwrap();
+    match analyse(&day8).unwrap() {
+        Analysis::NeverTerminates { ip, acc, .. } => assert_eq!((ip, acc), (370, 1446)),
+        a => panic!("unexpected {:?}", a),
+    }
+    let mut fixed = day8;
+    fixed[253] = fixed[253].flipped().unwrap();
+    assert_eq!(analyse(&fixed).unwrap(), Analysis::Terminates { acc: 1403, steps: 206 });
+    assert!(analyse(&[Mov(super::vm::Register::A, super::vm::Operand::Imm(1))]).is_err());
+}
+
+// Compares against the VM on random programs.
+#[test]
+fn test_analyse_differential() {
+    use super::vm::{VM, RunOutcome};
+    use super::testutil::Rng;
+    use Instruction::*;
+
+    let mut rng = Rng::new(0x9e37_79b9);
+    let mut next = |n: u64| rng.below(n) as i32;
+    for _ in 0..1000 {
+        let len = next(16);
+        let program = (0..len)
+            .map(|_| {
+                let offset = next(len as u64 + 4) - 2 - next(len as u64);
+                match next(4) {
+                    0 | 1 => Acc(next(21) - 10),
+                    2 => Nop(offset),
+                    _ => Jmp(offset),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut vm = VM::new(program.clone(), Default::default());
+        vm.set_recording(true);
+        let outcome = vm.run().unwrap();
+        let steps = vm.step_count() as u64;
+        let expected = match outcome {
+            RunOutcome::Terminated(state) => Analysis::Terminates { acc: state.acc, steps },
+            RunOutcome::JumpedOutOfRange { ip, state } => Analysis::JumpsOutOfRange { ip, acc: state.acc, steps },
+            RunOutcome::InfiniteLoop { ip, state, .. } => {
+                let cfg = Cfg::new(&program);
+                let body = cfg.loops().into_iter()
+                    .find(|l| l.contains(&cfg.block_of(ip as usize).unwrap()))
+                    .unwrap();
+                Analysis::NeverTerminates { ip, acc: state.acc, steps, body }
+            },
+            RunOutcome::OutOfFuel(_) => unreachable!(),
+        };
+        assert_eq!(analyse(&program).unwrap(), expected, "{:?}", program);
+    }
+}
@@ -1,85 +1,691 @@
 use nom::{
     IResult,
-    character::complete::{space0, alpha1, alphanumeric0},
-    number::complete::double,
-    combinator::recognize,
-    sequence::pair,
+    branch::alt,
+    bytes::complete::{is_not, take_while},
+    character::complete::{space0, space1, alpha1, digit1, char, one_of},
+    combinator::{opt, recognize, map},
+    sequence::{pair, delimited, terminated, tuple},
+    multi::separated_list1,
 };
-use super::vm::Instruction;
+use super::vm::{Instruction, Operand, Register};
 use nom::error::{ErrorKind};
+use nom::Offset;
 use anyhow::{anyhow, Error};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::ops::Range;
 
 #[derive(Debug)]
-pub struct NomError(pub anyhow::Error);
+pub struct NomError {
+    pub error: anyhow::Error,
+    // Length of the input remaining where the error occurred, if known.
+    remaining: Option<usize>,
+}
 
 impl NomError {
     pub fn fail<T: Into<anyhow::Error>>(v: T) -> nom::Err<NomError> {
-        nom::Err::Failure(NomError(v.into()))
+        nom::Err::Failure(NomError::from(v.into()))
+    }
+
+    pub fn fail_at<T: Into<anyhow::Error>>(input: &str, v: T) -> nom::Err<NomError> {
+        nom::Err::Failure(NomError{
+            error: v.into(),
+            remaining: Some(input.len()),
+        })
+    }
+
+    // Byte offset of the error within the input originally handed to the
+    // parser, which must have been `input_len` bytes long.
+    fn offset(&self, input_len: usize) -> Option<usize> {
+        self.remaining.map(|r| input_len.saturating_sub(r))
+    }
+
+    pub fn into_diagnostic(self, source: &str) -> Diagnostic {
+        let span = match self.offset(source.len()) {
+            Some(offset) => token_span(source, offset),
+            None => 0..source.len(),
+        };
+        Diagnostic::new(None, source, span, self.error)
     }
 }
 
 impl std::fmt::Display for NomError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        self.error.fmt(f)
     }
 }
 
 impl From<NomError> for anyhow::Error {
     fn from(e: NomError) -> Self {
-        e.0
+        e.error
     }
 }
 
 impl From<anyhow::Error> for NomError {
     fn from(e: Error) -> Self {
-        NomError(e)
+        NomError{
+            error: e,
+            remaining: None,
+        }
     }
 }
 
 impl nom::error::ParseError<&str> for NomError {
     fn from_error_kind(input: &str, kind: ErrorKind) -> Self {
-        NomError(anyhow!("error {} at: {}", kind.description(), input))
+        NomError{
+            error: anyhow!("expected {}", kind.description()),
+            remaining: Some(input.len()),
+        }
+    }
+
+    fn append(_input: &str, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub path: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+    pub span: Range<usize>,
+    pub message: String,
+    source_line: String,
+    line_start: usize,
+}
+
+impl Diagnostic {
+    pub fn new<M: std::fmt::Display>(path: Option<PathBuf>, source: &str, span: Range<usize>, message: M) -> Diagnostic {
+        let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[line_start..].find('\n').map_or(source.len(), |i| line_start + i);
+        let source_line = source[line_start..line_end].trim_end_matches('\r');
+
+        Diagnostic{
+            path,
+            line: source[..line_start].matches('\n').count() + 1,
+            column: source[line_start..span.start].chars().count() + 1,
+            span,
+            message: message.to_string(),
+            source_line: source_line.to_string(),
+            line_start,
+        }
     }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        writeln!(f, "error: {}", self.message)?;
+        match &self.path {
+            Some(path) => writeln!(f, "{}--> {}:{}:{}", gutter, path.display(), self.line, self.column)?,
+            None => writeln!(f, "{}--> {}:{}", gutter, self.line, self.column)?,
+        }
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.source_line)?;
 
-    fn append(input: &str, kind: ErrorKind, other: Self) -> Self {
-        NomError(other.0.context(format!("error {} at: {}", kind.description(), input)))
+        // Carets stop at the end of the first line for multi-line spans.
+        let start = self.span.start - self.line_start;
+        let end = (self.span.end - self.line_start).min(self.source_line.len()).max(start);
+        let padding = self.source_line[..start].chars().count();
+        let width = self.source_line[start..end].chars().count().max(1);
+        write!(f, "{} | {}{}", gutter, " ".repeat(padding), "^".repeat(width))
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[derive(Clone, Debug, Default)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl std::fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (idx, diagnostic) in self.0.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+                writeln!(f)?;
+            }
+            diagnostic.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+// The span of the whitespace-delimited token at `offset`, or a single
+// character if there is none.
+fn token_span(source: &str, offset: usize) -> Range<usize> {
+    let rest = &source[offset..];
+    let len = match rest.find(char::is_whitespace) {
+        Some(0) => rest.chars().next().map_or(0, char::len_utf8),
+        Some(len) => len,
+        None => rest.len(),
+    };
+    offset..offset + len
+}
+
+// Iterates over non-empty lines with surrounding whitespace removed, along
+// with their byte offset into `input`.
+fn lines_with_offsets(input: &str) -> impl Iterator<Item=(usize, &str)> {
+    input.split('\n')
+        .scan(0, |offset, line| {
+            let start = *offset;
+            *offset += line.len() + 1;
+            let trimmed = line.trim_start();
+            Some((start + line.len() - trimmed.len(), trimmed.trim_end()))
+        })
+        .filter(|(_, line)| !line.is_empty())
+}
+
+pub fn parse_lines<'a, T, F>(input: &'a str, mut parser: F) -> Result<Vec<T>, Diagnostics>
+    where F: FnMut(&'a str) -> IResult<&'a str, T, NomError> {
+    let mut result = Vec::new();
+    let mut diagnostics = Vec::new();
+
+    for (offset, line) in lines_with_offsets(input) {
+        match parser(line) {
+            Ok(("", value)) => result.push(value),
+            Ok((rest, _)) => {
+                let span = token_span(input, offset + line.len() - rest.len());
+                diagnostics.push(Diagnostic::new(None, input, span, "unexpected trailing input"));
+            },
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                let span = match e.offset(line.len()) {
+                    Some(o) => token_span(input, offset + o),
+                    None => offset..offset + line.len(),
+                };
+                diagnostics.push(Diagnostic::new(None, input, span, e.error));
+            },
+            Err(nom::Err::Incomplete(_)) => {
+                diagnostics.push(Diagnostic::new(None, input, offset..offset + line.len(), "incomplete input"));
+            },
+        }
+    }
+
+    if diagnostics.is_empty() {
+        Ok(result)
+    } else {
+        Err(Diagnostics(diagnostics))
     }
 }
 
 fn parse_i32(input: &str) -> IResult<&str, i32, NomError> {
-    let (input, d) = double(input)?;
-    Ok((input, d as i32))
+    let (rest, digits) = recognize(pair(opt(one_of("+-")), digit1))(input)?;
+    let v = digits.parse().map_err(|e| NomError::fail_at(input, e))?;
+    Ok((rest, v))
 }
 
-fn parse_instruction(input: &str) -> IResult<&str, Instruction, NomError> {
+pub(crate) fn parse_identifier(input: &str) -> IResult<&str, &str, NomError> {
+    recognize(pair(
+        alt((alpha1, recognize(char('_')))),
+        take_while(|c: char| c.is_alphanumeric() || c == '_')))(input)
+}
+
+#[derive(Clone, Debug)]
+enum Arg<'a> {
+    Imm(i32),
+    Reg(Register),
+    Symbol(&'a str),
+}
+
+fn parse_arg(input: &str) -> IResult<&str, Arg<'_>, NomError> {
+    alt((
+        map(parse_i32, Arg::Imm),
+        map(parse_identifier, |s| match Register::from_name(s) {
+            Some(r) => Arg::Reg(r),
+            None => Arg::Symbol(s),
+        }),
+    ))(input)
+}
+
+// Each mnemonic alongside a description of its operands.
+const MNEMONICS: &[(&str, &str)] = &[
+    ("acc", "imm"),
+    ("jmp", "offset"),
+    ("nop", "imm"),
+    ("mov", "reg, value"),
+    ("add", "reg, value"),
+    ("mul", "reg, value"),
+    ("mod", "reg, value"),
+    ("ld", "reg, addr"),
+    ("st", "addr, value"),
+    ("jz", "value, offset"),
+    ("jnz", "value, offset"),
+    ("jlt", "value, value, offset"),
+    ("in", "reg, port"),
+    ("out", "port, value"),
+    ("send", "channel, value"),
+    ("recv", "reg, channel"),
+];
+
+fn build_instruction(mnemonic: &str, args: &[Operand]) -> anyhow::Result<Instruction> {
+    use Operand::{Imm, Reg};
+
+    Ok(match (mnemonic, args) {
+        ("acc", [Imm(x)]) => Instruction::Acc(*x),
+        ("jmp", [Imm(x)]) => Instruction::Jmp(*x),
+        ("nop", [Imm(x)]) => Instruction::Nop(*x),
+        ("mov", [Reg(r), x]) => Instruction::Mov(*r, *x),
+        ("add", [Reg(r), x]) => Instruction::Add(*r, *x),
+        ("mul", [Reg(r), x]) => Instruction::Mul(*r, *x),
+        ("mod", [Reg(r), x]) => Instruction::Mod(*r, *x),
+        ("ld", [Reg(r), addr]) => Instruction::Load(*r, *addr),
+        ("st", [addr, x]) => Instruction::Store(*addr, *x),
+        ("jz", [x, Imm(offset)]) => Instruction::Jz(*x, *offset),
+        ("jnz", [x, Imm(offset)]) => Instruction::Jnz(*x, *offset),
+        ("jlt", [x, y, Imm(offset)]) => Instruction::Jlt(*x, *y, *offset),
+        ("in", [Reg(r), Imm(port)]) => Instruction::In(*r, *port),
+        ("out", [Imm(port), x]) => Instruction::Out(*port, *x),
+        ("send", [Imm(channel), x]) => Instruction::Send(*channel, *x),
+        ("recv", [Reg(r), Imm(channel)]) => Instruction::Recv(*r, *channel),
+        _ => {
+            let expected = MNEMONICS.iter()
+                .find(|(m, _)| *m == mnemonic)
+                .map_or("", |(_, operands)| operands);
+            Err(anyhow!("invalid operands, expected: {} {}", mnemonic, expected))?
+        },
+    })
+}
+
+enum Statement<'a> {
+    Const(&'a str, i32),
+    Include(&'a str),
+    Instruction(&'static str, Vec<Arg<'a>>, &'a str),
+}
+
+fn parse_directive(input: &str) -> IResult<&str, Statement<'_>, NomError> {
+    let (input, _) = char('.')(input)?;
+    let (rest, name) = parse_identifier(input)?;
+    let (rest, _) = space1(rest)?;
+
+    match name {
+        "const" => {
+            let (rest, symbol) = parse_identifier(rest)?;
+            let (rest, _) = space1(rest)?;
+            let (rest, value) = parse_i32(rest)?;
+            Ok((rest, Statement::Const(symbol, value)))
+        },
+        "include" => {
+            let (rest, path) = delimited(char('"'), is_not("\""), char('"'))(rest)?;
+            Ok((rest, Statement::Include(path)))
+        },
+        _ => Err(NomError::fail_at(input, anyhow!("no such directive: .{}", name))),
+    }
+}
+
+fn parse_instruction(input: &str) -> IResult<&str, Statement<'_>, NomError> {
+    let (rest, inst_name) = parse_identifier(input)?;
+    let (rest, _) = space0(rest)?;
+    let (rest, args) = separated_list1(tuple((space0, char(','), space0)), parse_arg)(rest)?;
+
+    let mnemonic = MNEMONICS.iter()
+        .map(|(m, _)| *m)
+        .find(|m| *m == inst_name)
+        .ok_or_else(|| NomError::fail_at(input, anyhow!("no such instruction: {}", inst_name)))?;
+    let text = &input[..input.offset(rest)];
+    Ok((rest, Statement::Instruction(mnemonic, args, text)))
+}
+
+type Line<'a> = (Option<&'a str>, Option<Statement<'a>>);
+
+fn parse_line(input: &str) -> IResult<&str, Line<'_>, NomError> {
     let (input, _) = space0(input)?;
-    let (input, inst_name) = recognize(pair(alpha1, alphanumeric0))(input)?;
+    let (input, label) = opt(terminated(parse_identifier, pair(space0, char(':'))))(input)?;
     let (input, _) = space0(input)?;
-    let (input, imm) = parse_i32(input)?;
+    let (input, statement) = opt(alt((parse_directive, parse_instruction)))(input)?;
     let (input, _) = space0(input)?;
+    let (input, _) = opt(pair(char(';'), take_while(|_| true)))(input)?;
+    Ok((input, (label, statement)))
+}
 
-    let inst = match inst_name {
-        "nop" => Instruction::Nop(imm),
-        "acc" => Instruction::Acc(imm),
-        "jmp" => Instruction::Jmp(imm),
-        _ => Err(nom::Err::Failure(NomError(anyhow!("no such instruction: {}", inst_name))))?,
-    };
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Symbol {
+    Label(usize),
+    Const(i32),
+}
 
-    Ok((input, inst))
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SourceLocation {
+    // An index into `Program::files`.
+    pub file: usize,
+    pub line: usize,
+    pub column: usize,
 }
 
-pub fn parse_asm(input: &str) -> IResult<&str, Vec<Instruction>, NomError> {
-    let mut result = Vec::new();
-    for line in input.lines().map(str::trim) {
-        if line.is_empty() {
-            continue;
+// An assembled program along with its symbols and where each instruction
+// came from. Files without a path were given as a string.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    pub symbols: BTreeMap<String, Symbol>,
+    pub files: Vec<Option<PathBuf>>,
+    pub source_map: Vec<SourceLocation>,
+}
+
+struct Pending {
+    mnemonic: &'static str,
+    // Operands, or the name and span of a symbol to resolve.
+    args: Vec<Result<Operand, (String, Range<usize>)>>,
+    file: usize,
+    span: Range<usize>,
+}
+
+#[derive(Default)]
+struct Assembler {
+    symbols: BTreeMap<String, Symbol>,
+    pending: Vec<Pending>,
+    files: Vec<(Option<PathBuf>, String)>,
+    include_stack: Vec<PathBuf>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Assembler {
+    fn define<F: FnOnce(&str, &str) -> Diagnostic>(&mut self, name: &str, symbol: Symbol, error: F) {
+        if Register::from_name(name).is_some() {
+            self.diagnostics.push(error(name, "register names cannot be used as symbols"));
+        } else if self.symbols.insert(name.to_string(), symbol).is_some() {
+            self.diagnostics.push(error(name, "duplicate symbol"));
+        }
+    }
+
+    fn include(&mut self, path: &Path) -> anyhow::Result<()> {
+        let canonical = path.canonicalize()?;
+        if self.include_stack.contains(&canonical) {
+            Err(anyhow!("recursive include"))?;
+        }
+
+        let source = std::fs::read_to_string(&canonical)?;
+        let dir = canonical.parent().map(Path::to_path_buf).unwrap_or_default();
+        self.include_stack.push(canonical);
+        self.add_source(Some(path.to_path_buf()), source, &dir);
+        self.include_stack.pop();
+        Ok(())
+    }
+
+    fn add_source(&mut self, path: Option<PathBuf>, source: String, dir: &Path) {
+        let file = self.files.len();
+        self.files.push((path.clone(), String::new()));
+
+        for (offset, line) in lines_with_offsets(&source) {
+            let span_of = |s: &str| {
+                let start = offset + line.offset(s);
+                start..start + s.len()
+            };
+            let error = |span: Range<usize>, message: anyhow::Error| {
+                Diagnostic::new(path.clone(), &source, span, message)
+            };
+
+            let (label, statement) = match parse_line(line) {
+                Ok(("", line)) => line,
+                Ok((rest, _)) => {
+                    let span = token_span(&source, offset + line.offset(rest));
+                    self.diagnostics.push(error(span, anyhow!("unexpected suffix on instruction")));
+                    continue
+                },
+                Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                    let span = match e.offset(line.len()) {
+                        Some(o) => token_span(&source, offset + o),
+                        None => span_of(line),
+                    };
+                    self.diagnostics.push(error(span, e.error));
+                    continue
+                },
+                Err(nom::Err::Incomplete(_)) => unreachable!("complete parsers only"),
+            };
+
+            let bad_symbol = |name: &str, message: &str| error(span_of(name), anyhow!("{}: {}", message, name));
+
+            if let Some(label) = label {
+                self.define(label, Symbol::Label(self.pending.len()), bad_symbol);
+            }
+
+            match statement {
+                Some(Statement::Const(name, value)) => self.define(name, Symbol::Const(value), bad_symbol),
+                Some(Statement::Include(include)) => {
+                    if let Err(e) = self.include(&dir.join(include)) {
+                        let e = e.context(format!("cannot include {}", include));
+                        self.diagnostics.push(error(span_of(include), e));
+                    }
+                },
+                Some(Statement::Instruction(mnemonic, args, text)) => {
+                    let args = args.into_iter()
+                        .map(|arg| match arg {
+                            Arg::Imm(imm) => Ok(Operand::Imm(imm)),
+                            Arg::Reg(r) => Ok(Operand::Reg(r)),
+                            Arg::Symbol(name) => Err((name.to_string(), span_of(name))),
+                        })
+                        .collect();
+                    self.pending.push(Pending{ mnemonic, args, file, span: span_of(text) });
+                },
+                None => {},
+            }
+        }
+
+        self.files[file].1 = source;
+    }
+
+    fn finish(mut self) -> Result<Program, Diagnostics> {
+        let mut result = Vec::with_capacity(self.pending.len());
+        let mut source_map = Vec::with_capacity(self.pending.len());
+        for (addr, pending) in self.pending.iter().enumerate() {
+            let (path, source) = &self.files[pending.file];
+            let mut operands = Vec::with_capacity(pending.args.len());
+            for arg in pending.args.iter() {
+                match arg {
+                    Ok(operand) => operands.push(*operand),
+                    Err((name, span)) => match self.symbols.get(name) {
+                        Some(Symbol::Label(target)) => operands.push(Operand::Imm(*target as i32 - addr as i32)),
+                        Some(Symbol::Const(value)) => operands.push(Operand::Imm(*value)),
+                        None => self.diagnostics.push(Diagnostic::new(
+                            path.clone(), source, span.clone(),
+                            format!("undefined symbol: {}", name))),
+                    },
+                }
+            }
+
+            if operands.len() != pending.args.len() {
+                continue
+            }
+
+            match build_instruction(pending.mnemonic, &operands) {
+                Ok(inst) => {
+                    let line_start = source[..pending.span.start].rfind('\n').map_or(0, |i| i + 1);
+                    source_map.push(SourceLocation{
+                        file: pending.file,
+                        line: source[..line_start].matches('\n').count() + 1,
+                        column: source[line_start..pending.span.start].chars().count() + 1,
+                    });
+                    result.push(inst);
+                },
+                Err(e) => self.diagnostics.push(Diagnostic::new(
+                    path.clone(), source, pending.span.clone(), e)),
+            }
+        }
+
+        if self.diagnostics.is_empty() {
+            Ok(Program{
+                instructions: result,
+                symbols: self.symbols,
+                files: self.files.into_iter().map(|(path, _)| path).collect(),
+                source_map,
+            })
+        } else {
+            // Symbols are only resolved here, after every other check, so put
+            // their errors back in file and line order.
+            self.diagnostics.sort_by(|a, b| (&a.path, a.line, a.column).cmp(&(&b.path, b.line, b.column)));
+            Err(Diagnostics(self.diagnostics))
+        }
+    }
+}
+
+pub fn assemble(input: &str) -> Result<Program, Diagnostics> {
+    let mut assembler = Assembler::default();
+    assembler.add_source(None, input.to_string(), Path::new(""));
+    assembler.finish()
+}
+
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Program> {
+    let mut assembler = Assembler::default();
+    assembler.include(path.as_ref())?;
+    Ok(assembler.finish()?)
+}
+
+pub fn parse_asm(input: &str) -> Result<Vec<Instruction>, Diagnostics> {
+    assemble(input).map(|program| program.instructions)
+}
+
+pub fn parse_asm_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<Instruction>> {
+    assemble_file(path).map(|program| program.instructions)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DisassembleOptions {
+    pub labels: bool,
+    pub addresses: bool,
+}
+
+pub fn disassemble(instructions: &[Instruction], options: &DisassembleOptions) -> String {
+    let len = instructions.len() as i32;
+    let label_target = |addr: usize, inst: &Instruction| {
+        inst.branch_target(addr as i32)
+            .filter(|target| options.labels && *target >= 0 && *target <= len)
+    };
+
+    let mut targets = vec![false; instructions.len() + 1];
+    for (addr, inst) in instructions.iter().enumerate() {
+        if let Some(target) = label_target(addr, inst) {
+            targets[target as usize] = true;
         }
+    }
+
+    let indent = if options.labels { "    " } else { "" };
+    let mut output = String::new();
+    for (addr, is_target) in targets.iter().copied().enumerate() {
+        if is_target {
+            output.push_str(&format!("l{}:\n", addr));
+        }
+
+        let inst = match instructions.get(addr) {
+            Some(inst) => inst,
+            None => break,
+        };
 
-        let (input, inst) = parse_instruction(line)?;
-        if !input.is_empty() {
-            Err(nom::Err::Error(NomError(anyhow!("unexpected suffix on instruction: {}", input))))?;
+        let label = label_target(addr, inst).map(|target| format!("l{}", target));
+        let mut text = String::new();
+        inst.write_asm(&mut text, label.as_deref()).unwrap();
+
+        if options.addresses {
+            output.push_str(&format!("{}{:<12} ; {:04}\n", indent, text, addr));
+        } else {
+            output.push_str(&format!("{}{}\n", indent, text));
         }
-        result.push(inst);
     }
-    Ok(("", result))
+
+    output
+}
+
+#[test]
+fn test_parse_asm() {
+    use Instruction::*;
+
+    let program = "\
+        .const STEP 3 ; loop increment
+        start:
+            nop +0
+        loop: acc STEP
+            jmp done
+            jmp loop
+        done:
+            jmp start
+        ";
+    assert_eq!(parse_asm(program).unwrap(), vec![
+        Nop(0), Acc(3), Jmp(2), Jmp(-2), Jmp(-4),
+    ]);
+
+    assert!(parse_asm("jmp nowhere").is_err());
+    assert!(parse_asm("a: nop +0\na: nop +0").is_err());
+}
+
+#[test]
+fn test_disassemble_round_trip() {
+    let program = parse_asm(include_str!("bin/day8part1.txt")).unwrap();
+    let all_options = [
+        DisassembleOptions{ labels: false, addresses: false },
+        DisassembleOptions{ labels: true, addresses: false },
+        DisassembleOptions{ labels: false, addresses: true },
+        DisassembleOptions{ labels: true, addresses: true },
+    ];
+
+    for options in all_options.iter() {
+        let text = disassemble(&program, options);
+        assert_eq!(parse_asm(&text).unwrap(), program, "options: {:?}", options);
+    }
+
+    let program = vec![Instruction::Jmp(2), Instruction::Acc(-1), Instruction::Jmp(-2), Instruction::Nop(7)];
+    let text = disassemble(&program, &DisassembleOptions{ labels: true, addresses: true });
+    assert_eq!(text, concat!(
+        "l0:\n",
+        "    jmp l2       ; 0000\n",
+        "    acc -1       ; 0001\n",
+        "l2:\n",
+        "    jmp l0       ; 0002\n",
+        "    nop +7       ; 0003\n",
+    ));
+}
+
+#[test]
+fn test_diagnostics() {
+    let errors = parse_asm("nop +0\n  bad +1\njmp nowhere\nacc +1 extra\n").unwrap_err();
+    let positions = errors.0.iter()
+        .map(|d| (d.line, d.column, d.span.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(positions, vec![(2, 3, 9..12), (3, 5, 20..27), (4, 8, 35..40)]);
+    assert_eq!(errors.0[1].message, "undefined symbol: nowhere");
+
+    assert_eq!(errors.0[0].to_string(), concat!(
+        "error: no such instruction: bad\n",
+        " --> 2:3\n",
+        "  |\n",
+        "2 |   bad +1\n",
+        "  |   ^^^",
+    ));
+}
+
+#[test]
+fn test_extended_asm() {
+    use Instruction::*;
+
+    let program = "\
+        .const PORT 2
+        loop:
+            in a, PORT
+            jz a, done
+            ld b, a
+            add b, 1
+            st a, b
+            mul acc, -3
+            jlt acc, 100, loop
+        done:
+            out 1, acc
+        ";
+    let instructions = parse_asm(program).unwrap();
+    assert_eq!(instructions, vec![
+        In(Register::A, 2),
+        Jz(Operand::Reg(Register::A), 6),
+        Load(Register::B, Operand::Reg(Register::A)),
+        Add(Register::B, Operand::Imm(1)),
+        Store(Operand::Reg(Register::A), Operand::Reg(Register::B)),
+        Mul(Register::Acc, Operand::Imm(-3)),
+        Jlt(Operand::Reg(Register::Acc), Operand::Imm(100), -6),
+        Out(1, Operand::Reg(Register::Acc)),
+    ]);
+
+    for labels in [false, true].iter().copied() {
+        let text = disassemble(&instructions, &DisassembleOptions{ labels, addresses: false });
+        assert_eq!(parse_asm(&text).unwrap(), instructions);
+    }
+
+    assert!(parse_asm("mov 1, a").is_err());
+    assert!(parse_asm("a: nop +0").is_err());
+    assert!(parse_asm("jz a").is_err());
 }
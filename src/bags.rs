@@ -39,11 +39,13 @@ fn parse_bag_count(input: &str) -> IResult<&str, (usize, &str)> {
     Ok((input, (count, bag_name)))
 }
 
-fn parse_bag_list(input: &str) -> IResult<&str, Vec<(usize, &str)>> {
+type BagList<'a> = Vec<(usize, &'a str)>;
+
+fn parse_bag_list(input: &str) -> IResult<&str, BagList<'_>> {
     separated_list1(pair(char(','), space1), parse_bag_count)(input)
 }
 
-fn parse_statement(input: &str) -> IResult<&str, (&str, Vec<(usize, &str)>)> {
+fn parse_statement(input: &str) -> IResult<&str, (&str, BagList<'_>)> {
     let (input, bag_name) = parse_bag_name(input)?;
     let input = skip_whitespace(input);
     let (input, _) = tag("contain")(input)?;
@@ -0,0 +1,41 @@
+use clap::{Arg, App};
+use aoc2020::asm::{disassemble, DisassembleOptions};
+use aoc2020::bytecode::{self, EncodeOptions};
+
+fn main() -> anyhow::Result<()> {
+    let args = App::new("asm")
+        .about("assembles a program to bytecode, or disassembles bytecode")
+        .arg(Arg::with_name("input")
+            .required(true))
+        .arg(Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .takes_value(true)
+            .required_unless("disassemble"))
+        .arg(Arg::with_name("strip")
+            .long("strip")
+            .help("omit the symbol table and source map"))
+        .arg(Arg::with_name("disassemble")
+            .short("d")
+            .long("disassemble")
+            .conflicts_with("output")
+            .help("print the program as text assembly"))
+        .get_matches();
+
+    let program = bytecode::load(args.value_of("input").unwrap())?;
+    if args.is_present("disassemble") {
+        print!("{}", disassemble(&program.instructions, &DisassembleOptions{ labels: true, addresses: true }));
+        return Ok(());
+    }
+
+    let strip = args.is_present("strip");
+    let options = EncodeOptions{
+        symbols: !strip,
+        source_map: !strip,
+    };
+    let output = args.value_of("output").unwrap();
+    let bytes = bytecode::encode(&program, &options);
+    std::fs::write(output, &bytes)?;
+    eprintln!("{} instructions, {} bytes written to {}", program.instructions.len(), bytes.len(), output);
+    Ok(())
+}
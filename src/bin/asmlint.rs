@@ -0,0 +1,83 @@
+use clap::{Arg, App};
+use anyhow::anyhow;
+use aoc2020::bytecode;
+use aoc2020::lint::{lint_program, LintKind};
+
+fn main() -> anyhow::Result<()> {
+    let mut names = vec!["warnings"];
+    names.extend_from_slice(LintKind::NAMES);
+
+    let args = App::new("asmlint")
+        .arg(Arg::with_name("program")
+            .required(true)
+            .multiple(true))
+        .arg(Arg::with_name("deny")
+            .short("D")
+            .long("deny")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .possible_values(&names)
+            .help("turn a lint, or all `warnings`, into an error"))
+        .arg(Arg::with_name("allow")
+            .short("A")
+            .long("allow")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .possible_values(&names)
+            .help("silence a lint, or all `warnings`"))
+        .get_matches();
+
+    let listed = |arg: &str, name: &str| args.values_of(arg)
+        .is_some_and(|mut v| v.any(|x| x == name));
+
+    // A lint named on its own takes precedence over `warnings`, and deny over
+    // allow. None means the lint is allowed, otherwise whether it is denied.
+    let level = |name: &str| {
+        for name in [name, "warnings"].iter() {
+            if listed("deny", name) {
+                return Some(true);
+            }
+            if listed("allow", name) {
+                return None;
+            }
+        }
+        Some(false)
+    };
+
+    let mut warnings = 0;
+    let mut errors = 0;
+    for path in args.values_of("program").unwrap() {
+        let program = bytecode::load(path)?;
+        for l in lint_program(&program) {
+            let name = l.kind.name();
+            let deny = match level(name) {
+                Some(deny) => deny,
+                None => continue,
+            };
+
+            let location = match l.location {
+                Some(loc) => {
+                    let file = program.files[loc.file].as_deref().map_or(path.into(), |p| p.display().to_string());
+                    format!("{}:{}:{}", file, loc.line, loc.column)
+                },
+                None => format!("{}:ip {}", path, l.ip),
+            };
+            let level = if deny { "error" } else { "warning" };
+            println!("{}: {}[{}]: {}", location, level, name, l);
+
+            if deny {
+                errors += 1;
+            } else {
+                warnings += 1;
+            }
+        }
+    }
+
+    eprintln!("{} warnings, {} errors", warnings, errors);
+    if errors > 0 {
+        Err(anyhow!("{} denied lints", errors))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use std::io::Read;
+use aoc2020::asm::parse_asm;
+use aoc2020::cfg::Cfg;
+use aoc2020::analysis::{analyse, Analysis};
+
+fn main() -> anyhow::Result<()> {
+    let mut contents = String::new();
+    std::io::stdin().read_to_string(&mut contents)?;
+    let asm = parse_asm(&contents)?;
+
+    let cfg = Cfg::new(&asm);
+    print!("{}", cfg.to_dot(&asm));
+
+    let unreachable = cfg.unreachable_blocks();
+    let loops = cfg.loops();
+    eprintln!("{} blocks, {} unreachable, {} loops", cfg.blocks().len(), unreachable.len(), loops.len());
+
+    match analyse(&asm) {
+        Ok(Analysis::Terminates { acc, steps }) => eprintln!("terminates after {} steps with acc {}", steps, acc),
+        Ok(Analysis::JumpsOutOfRange { ip, acc, steps }) => eprintln!("jumps out of range to {} after {} steps with acc {}", ip, steps, acc),
+        Ok(Analysis::NeverTerminates { ip, acc, steps, body }) =>
+            eprintln!("never terminates: repeats {} after {} steps with acc {}, in a loop of {} blocks", ip, steps, acc, body.len()),
+        Err(e) => eprintln!("not analysed: {}", e),
+    }
+
+    Ok(())
+}
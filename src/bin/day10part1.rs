@@ -20,7 +20,7 @@ fn main() -> anyhow::Result<()> {
 
     while let Some((idx, num)) = num_iter.next() {
         let delta = num - last_num;
-        if delta < 1 || delta > 3 {
+        if !(1..=3).contains(&delta) {
             Err(anyhow!("invalid delta {} ({} -> {})", delta, last_num, num))?;
         }
         last_num = num;
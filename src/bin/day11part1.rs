@@ -1,8 +1,8 @@
 use std::io::Read;
 use anyhow::anyhow;
 
-const EMPTY: u8 = 'L' as u8;
-const OCCUPIED: u8 = '#' as u8;
+const EMPTY: u8 = b'L';
+const OCCUPIED: u8 = b'#';
 
 fn main() -> anyhow::Result<()> {
     let mut contents = String::new();
@@ -61,9 +61,8 @@ fn main() -> anyhow::Result<()> {
                     continue
                 }
                 let t_idx = (t_x + (t_y * iwidth)) as usize;
-                match src[t_idx] {
-                    OCCUPIED => n_occupied += 1,
-                    _ => {},
+                if src[t_idx] == OCCUPIED {
+                    n_occupied += 1;
                 }
             }
 
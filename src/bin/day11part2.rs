@@ -1,8 +1,8 @@
 use std::io::Read;
 use anyhow::anyhow;
 
-const EMPTY: u8 = 'L' as u8;
-const OCCUPIED: u8 = '#' as u8;
+const EMPTY: u8 = b'L';
+const OCCUPIED: u8 = b'#';
 
 fn main() -> anyhow::Result<()> {
     let mut contents = String::new();
@@ -35,7 +35,7 @@ fn main() -> anyhow::Result<()> {
         .collect::<Result<Vec<_>, _>>()?;
 
     let mut best = None;
-    for bus in busses.iter().copied().filter_map(|x| x) {
+    for bus in busses.iter().copied().flatten() {
         let (_, w) = pos_div_mod(departure, bus);
         if let Some((wait, _)) = best {
             if wait > w {
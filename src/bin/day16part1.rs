@@ -88,7 +88,7 @@ impl Notes {
             ticket,
             Vec::new(),
             |mut acc, item| {
-                acc.extend(item.into_iter());
+                acc.extend(item);
                 acc
             })(input)?;
 
@@ -108,7 +108,9 @@ fn main() -> anyhow::Result<()> {
     let mut contents = String::new();
     std::io::stdin().read_to_string(&mut contents)?;
 
-    let (_, notes) = all_consuming(Notes::parse)(&contents).finish()?;
+    let (_, notes) = all_consuming(Notes::parse)(&contents)
+        .finish()
+        .map_err(|e| e.into_diagnostic(&contents))?;
     let ticket_len = notes.fields.len();
 
     let mut bad_sum = 0;
@@ -10,7 +10,7 @@ fn main() -> Result<()> {
     let entries = contents
         .split('\n')
         .map(str::trim)
-        .filter(|s| s.len() > 0)
+        .filter(|s| !s.is_empty())
         .map(i32::from_str)
         .collect::<std::result::Result<Vec<_>, _>>()?;
 
@@ -23,7 +23,7 @@ fn main() -> anyhow::Result<()> {
         let mut trees = 0;
 
         while y < slope.height() {
-            if slope.get(x, y) == Some('#' as u8) {
+            if slope.get(x, y) == Some(b'#') {
                 trees += 1;
             }
 
@@ -33,7 +33,7 @@ fn main() -> anyhow::Result<()> {
 
         println!("trees ({}, {}) {}", dx, dy, trees);
 
-        product = product * (trees as i64);
+        product *= trees as i64;
     }
 
     println!("product {}", product);
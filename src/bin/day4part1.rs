@@ -1,36 +1,90 @@
 use std::io::Read;
 use std::fmt::Write;
+use std::collections::BTreeMap;
+use clap::{Arg, App};
+use aoc2020::kv;
 use aoc2020::passport;
 use aoc2020::passport::Passport;
+use aoc2020::rules::Rules;
 
 fn main() -> anyhow::Result<()> {
+    let args = App::new("day4part1")
+        .arg(Arg::with_name("rules")
+            .long("rules")
+            .takes_value(true)
+            .help("validation rules as JSON, instead of the defaults"))
+        .arg(Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .takes_value(true)
+            .help("write the validated passports here, in the input format"))
+        .get_matches();
+
+    let rules = match args.value_of("rules") {
+        Some(path) => Rules::load(path)?,
+        None => passport::default_rules().clone(),
+    };
+
     let mut contents = String::new();
     std::io::stdin().read_to_string(&mut contents)?;
 
     let mut num_valid = 0;
     let mut num_validated = 0;
     let mut debug = String::new();
+    // Each validated passport as a block in the input format.
+    let mut validated = Vec::new();
 
     for block in passport::split_blocks(&contents) {
-        match Passport::parse(block) {
-            Ok((_, p)) => {
-                num_valid += 1;
-                println!("valid passport: {}", serde_json::to_string(&p)?);
+        let fields = match kv::from_block::<BTreeMap<String, String>>(&contents, block) {
+            Ok(fields) => fields,
+            Err(e) => {
+                println!("invalid passport: {}", e);
+                continue
+            },
+        };
+
+        let missing = passport::REQUIRED_FIELDS.iter()
+            .filter(|f| !fields.contains_key(**f))
+            .copied()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            println!("invalid passport: missing {}", missing.join(", "));
+            continue
+        }
 
-                match p.validate() {
-                    Ok(()) => {
-                        num_validated += 1;
-                        println!(" validated passport");
-                    },
-                    Err(e) => {
-                        println!(" not validated passport: {}", e);
-                        write!(&mut debug, "-- {}\n{}\n\n", serde_json::to_string(&p)?, e)?;
-                    },
+        num_valid += 1;
+        let report = match kv::from_block::<Passport>(&contents, block) {
+            Ok(p) => {
+                println!("valid passport: {}", serde_json::to_string(&p)?);
+                let report = p.validate_with(&rules);
+                if report.is_valid() {
+                    validated.push(kv::to_string(&p)?);
                 }
+                report
             },
+            // The typed fields stop at the first bad one, so check the rest
+            // of the raw fields too. Lenient rules may still pass them, and
+            // then the raw fields are what gets written.
             Err(e) => {
-                println!("invalid passport: {}", e);
+                println!("valid passport: {}", serde_json::to_string(&fields)?);
+                println!(" {}", e);
+                let report = rules.validate(&fields);
+                if report.is_valid() {
+                    validated.push(kv::to_string(&fields)?);
+                }
+                report
             },
+        };
+
+        if report.is_valid() {
+            num_validated += 1;
+            println!(" validated passport");
+        } else {
+            println!(" not validated passport:");
+            for e in report.errors.iter() {
+                println!("  {}", e);
+            }
+            write!(&mut debug, "-- {}\n{}\n\n", serde_json::to_string(&fields)?, serde_json::to_string(&report)?)?;
         }
     }
 
@@ -38,5 +92,9 @@ fn main() -> anyhow::Result<()> {
     println!("num validated {}", num_validated);
 
     println!("\n\n{}", debug);
+
+    if let Some(path) = args.value_of("output") {
+        std::fs::write(path, validated.join("\n\n") + "\n")?;
+    }
     Ok(())
 }
@@ -8,11 +8,11 @@ fn binary_partition(s: &str) -> anyhow::Result<i16> {
         match c {
             'F' | 'L' => {},
             'B' | 'R' => {
-                v = v | next;
+                v |= next;
             },
             c => Err(anyhow::Error::msg(format!("unexpected char {}", c)))?,
         };
-        next = next >> 1;
+        next >>= 1;
     }
 
     Ok(v)
@@ -25,7 +25,7 @@ fn boarding_pass_to_coords(p: &str) -> anyhow::Result<(i16, i16)> {
 
     let row = binary_partition(&p[..7])?;
     let col = binary_partition(&p[7..])?;
-    return Ok((row, col))
+    Ok((row, col))
 }
 
 fn boarding_pass_to_seat(p: &str) -> anyhow::Result<i16> {
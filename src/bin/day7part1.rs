@@ -9,7 +9,7 @@ fn invert_mappings(mappings: &BTreeMap<String, BTreeMap<String, usize>>) -> BTre
         for value in mapping.keys() {
             let set = inverted_mappings
                 .entry(value.clone())
-                .or_insert_with(|| BTreeSet::new());
+                .or_insert_with(BTreeSet::new);
             set.insert(key.clone());
         }
     }
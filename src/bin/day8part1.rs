@@ -1,55 +1,38 @@
 use std::io::Read;
-use std::collections::BTreeSet;
-use aoc2020::vm::{VM, Instruction};
+use clap::{Arg, App};
+use aoc2020::vm::{self, VM, RunOutcome};
 use aoc2020::asm::parse_asm;
+use aoc2020::snapshot::SnapshotOptions;
 
 fn main() -> anyhow::Result<()> {
+    let args = App::new("day8part1")
+        .arg(Arg::with_name("snapshot")
+            .long("snapshot")
+            .takes_value(true)
+            .help("write a snapshot here if a loop is detected (.json for JSON, otherwise binary)"))
+        .get_matches();
+
     let mut contents = String::new();
     std::io::stdin().read_to_string(&mut contents)?;
-    let asm = parse_asm(&contents)?.1;
-    let mut seen = BTreeSet::new();
-
-    let mut contenders = Vec::new();
+    let asm = parse_asm(&contents)?;
 
     let mut vm = VM::new(asm, Default::default());
-    while seen.insert(vm.state().ip) {
-        let ip = vm.state().ip;
-        if let Some(Instruction::Nop(_)) = vm.instructions().get(ip as usize) {
-            contenders.push(ip);
-        }
+    vm.set_recording(args.is_present("snapshot"));
+    let outcome = vm.run()?;
+    println!("acc {} ip {}", outcome.state().acc, outcome.state().ip);
 
-        vm.step()?;
-        let nip = vm.state().ip;
-        if nip != ip + 1 {
-            contenders.push(ip);
-        }
+    if outcome.is_terminated() {
+        return Ok(());
     }
 
-    println!("acc {} ip {}", vm.state().acc, vm.state().ip);
-
-    for contender in contenders {
-        let contender = contender as usize;
-        let mut inst = vm.instructions().to_vec();
-
-        match inst[contender] {
-            Instruction::Nop(x) => inst[contender] = Instruction::Jmp(x),
-            Instruction::Jmp(x) => inst[contender] = Instruction::Nop(x),
-            _ => unreachable!(),
-        }
-
-        println!("trying {}", contender);
-
-        let mut seen = BTreeSet::new();
-        let mut vm = VM::new(inst, Default::default());
-        while seen.insert(vm.state().ip) {
-            let ip = vm.state().ip;
-            if ip == vm.instructions().len() as i32 {
-                println!("it works {}, acc {}", contender, vm.state().acc);
-                break;
-            }
+    if let (RunOutcome::InfiniteLoop { .. }, Some(path)) = (&outcome, args.value_of("snapshot")) {
+        vm.snapshot(&SnapshotOptions::default()).save(path)?;
+        eprintln!("snapshot written to {}", path);
+    }
 
-            vm.step()?;
-        }
+    match vm::repair(vm.instructions())? {
+        Some(repair) => println!("it works {}, acc {}", repair.ip, repair.state.acc),
+        None => println!("no single patch terminates"),
     }
 
     Ok(())
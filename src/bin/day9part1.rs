@@ -15,7 +15,7 @@ fn find_sum(src: &[i64], target: i64) -> bool {
         }
     }
 
-    return false
+    false
 }
 
 fn find_contiguous_sum(src: &[i64], target: i64) -> Option<(usize, usize)> {
@@ -62,7 +62,6 @@ fn main() -> anyhow::Result<()> {
         Err(anyhow!("not enough input for window"))?;
     }
 
-    let mut offset = window_size;
     let mut window = Vec::with_capacity(window_size);
 
     let mut nums_iter = nums.iter().copied();
@@ -70,7 +69,7 @@ fn main() -> anyhow::Result<()> {
 
     let mut to_test = None;
 
-    for num in nums_iter {
+    for (offset, num) in (window_size..).zip(nums_iter) {
         if !find_sum(&window, num) {
             println!("not found {} - {}", num, offset);
             to_test = Some(num);
@@ -78,13 +77,12 @@ fn main() -> anyhow::Result<()> {
 
         let write_idx = offset % window.len();
         window[write_idx] = num;
-        offset += 1;
     }
 
     if let Some(to_test) = to_test {
         if let Some((start, end)) = find_contiguous_sum(&nums, to_test) {
             let range = &nums[start..end];
-            let (min, max) = range.iter().copied().fold((i64::max_value(), i64::min_value()), |(min, max), x| {
+            let (min, max) = range.iter().copied().fold((i64::MAX, i64::MIN), |(min, max), x| {
                 (min.min(x), max.max(x))
             });
             println!("found {} -> {} = {} + {} = {}", start, end, min, max, min + max);
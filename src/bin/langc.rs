@@ -0,0 +1,35 @@
+use clap::{Arg, App};
+use aoc2020::asm::Program;
+use aoc2020::bytecode::{self, EncodeOptions};
+use aoc2020::lang;
+
+fn main() -> anyhow::Result<()> {
+    let args = App::new("langc")
+        .about("compiles a program in the structured language to assembly or bytecode")
+        .arg(Arg::with_name("input")
+            .required(true))
+        .arg(Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .takes_value(true)
+            .help("write bytecode here instead of printing assembly"))
+        .get_matches();
+
+    let source = std::fs::read_to_string(args.value_of("input").unwrap())?;
+    let output = match args.value_of("output") {
+        Some(output) => output,
+        None => {
+            print!("{}", lang::compile_to_asm(&source)?);
+            return Ok(());
+        },
+    };
+
+    let program = Program{
+        instructions: lang::compile(&source)?,
+        ..Default::default()
+    };
+    let bytes = bytecode::encode(&program, &EncodeOptions::default());
+    std::fs::write(output, &bytes)?;
+    eprintln!("{} instructions, {} bytes written to {}", program.instructions.len(), bytes.len(), output);
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use clap::{Arg, App};
+use aoc2020::vm::{VM, RunOutcome, Arithmetic};
+use aoc2020::bytecode;
+
+fn main() -> anyhow::Result<()> {
+    let args = App::new("run")
+        .about("runs a program given as text assembly or bytecode")
+        .arg(Arg::with_name("program")
+            .required(true))
+        .arg(Arg::with_name("fuel")
+            .short("f")
+            .long("fuel")
+            .takes_value(true))
+        .arg(Arg::with_name("width")
+            .long("width")
+            .takes_value(true)
+            .possible_values(&["i32", "i64", "i128"])
+            .default_value("i32"))
+        .arg(Arg::with_name("overflow")
+            .long("overflow")
+            .takes_value(true)
+            .possible_values(&["wrapping", "saturating", "checked"])
+            .default_value("checked"))
+        .get_matches();
+    let arithmetic = Arithmetic{
+        width: args.value_of("width").unwrap().parse()?,
+        overflow: args.value_of("overflow").unwrap().parse()?,
+    };
+
+    let program = bytecode::load(args.value_of("program").unwrap())?;
+    let mut vm = VM::new(program.instructions, Default::default());
+    vm.set_arithmetic(arithmetic)?;
+    let outcome = match args.value_of("fuel") {
+        Some(fuel) => vm.run_with_fuel(fuel.parse()?)?,
+        None => vm.run()?,
+    };
+
+    let state = outcome.state();
+    match &outcome {
+        RunOutcome::Terminated(_) => println!("terminated"),
+        RunOutcome::InfiniteLoop { ip, .. } => println!("infinite loop at {}", ip),
+        RunOutcome::JumpedOutOfRange { ip, .. } => println!("jumped out of range to {}", ip),
+        RunOutcome::OutOfFuel(_) => println!("out of fuel"),
+    }
+    println!("ip {} acc {} a {} b {} c {} d {}", state.ip, state.acc, state.regs[0], state.regs[1], state.regs[2], state.regs[3]);
+    Ok(())
+}
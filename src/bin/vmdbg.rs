@@ -0,0 +1,377 @@
+use std::io::{BufRead, Write};
+use std::collections::{BTreeSet, HashSet};
+use clap::{Arg, App};
+use anyhow::anyhow;
+use aoc2020::vm::{VM, Instruction, Arithmetic, DEFAULT_FUEL};
+use aoc2020::asm::parse_asm;
+use aoc2020::bytecode;
+use aoc2020::snapshot::{Snapshot, SnapshotOptions};
+
+const HELP: &str = "\
+commands:
+  s, step [n]           execute n instructions (default 1)
+  c, continue           run until a breakpoint, watchpoint, loop, exit or
+                        the fuel budget runs out
+  b, break <ip>         set a breakpoint
+  d, delete <ip>        remove a breakpoint
+  w, watch acc <op> <n> stop when acc becomes <op> n (==, !=, <, <=, >, >=)
+  unwatch <idx>         remove a watchpoint
+  i, info               list breakpoints and watchpoints
+  p, print              print the current state
+  l, list [ip]          list instructions around ip
+  patch <ip> <inst>     replace the instruction at ip, e.g. `patch 3 nop +1`
+  record on|off         record history so execution can be reversed
+  back [n]              step backwards n instructions (default 1)
+  goto <step>           move to a step number, forwards or backwards
+  lastacc               find the step and ip that last changed acc
+  save <path>           write a snapshot (.json for JSON, otherwise binary)
+  r, restart            reset the state, keeping patches
+  reload                reset the state and undo all patches
+  q, quit               exit the debugger";
+
+#[derive(Copy, Clone, Debug)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn parse(s: &str) -> anyhow::Result<Comparison> {
+        Ok(match s {
+            "==" => Comparison::Eq,
+            "!=" => Comparison::Ne,
+            "<" => Comparison::Lt,
+            "<=" => Comparison::Le,
+            ">" => Comparison::Gt,
+            ">=" => Comparison::Ge,
+            _ => Err(anyhow!("unknown comparison: {}", s))?,
+        })
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        }
+    }
+
+    fn test(&self, a: i128, b: i128) -> bool {
+        match self {
+            Comparison::Eq => a == b,
+            Comparison::Ne => a != b,
+            Comparison::Lt => a < b,
+            Comparison::Le => a <= b,
+            Comparison::Gt => a > b,
+            Comparison::Ge => a >= b,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Watchpoint {
+    comparison: Comparison,
+    value: i128,
+}
+
+impl Watchpoint {
+    fn test(&self, acc: i128) -> bool {
+        self.comparison.test(acc, self.value)
+    }
+}
+
+struct Debugger {
+    vm: VM,
+    breakpoints: BTreeSet<i32>,
+    watchpoints: Vec<Watchpoint>,
+    fuel: usize,
+}
+
+impl Debugger {
+    fn reset(&mut self, instructions: Vec<Instruction>) -> anyhow::Result<()> {
+        let recording = self.vm.is_recording();
+        let arithmetic = self.vm.arithmetic();
+        self.vm = VM::new(instructions, Default::default());
+        self.vm.set_arithmetic(arithmetic)?;
+        self.vm.set_recording(recording);
+        self.print_state();
+        Ok(())
+    }
+
+    fn print_state(&self) {
+        let state = self.vm.state();
+        if self.vm.is_recording() {
+            print!("step {} ", self.vm.step_count());
+        }
+
+        print!("ip {} acc {}", state.ip, state.acc);
+        if state.regs.iter().any(|r| *r != 0) {
+            print!(" a {} b {} c {} d {}", state.regs[0], state.regs[1], state.regs[2], state.regs[3]);
+        }
+
+        let inst = self.vm.instructions().get(state.ip as usize);
+        match inst {
+            Some(inst) if state.ip >= 0 => println!(": {}", inst),
+            _ if self.vm.is_terminated() => println!(": terminated"),
+            _ => println!(": out of range"),
+        }
+    }
+
+    fn list(&self, centre: i32) {
+        let len = self.vm.instructions().len() as i32;
+        let start = (centre - 5).max(0);
+        let end = (centre + 6).min(len);
+        for ip in start..end {
+            let marker = if ip == self.vm.state().ip { "=>" } else { "  " };
+            let bp = if self.breakpoints.contains(&ip) { "*" } else { " " };
+            println!("{}{} {:04} {}", marker, bp, ip, self.vm.instructions()[ip as usize]);
+        }
+    }
+
+    // Steps once, reporting whether any watchpoint went from false to true.
+    fn step(&mut self) -> anyhow::Result<bool> {
+        let before = self.vm.state().acc;
+        self.vm.step()?;
+        let after = self.vm.state().acc;
+
+        let mut triggered = false;
+        for (idx, w) in self.watchpoints.iter().enumerate() {
+            if !w.test(before) && w.test(after) {
+                println!("watchpoint {} hit: acc {} -> {}", idx, before, after);
+                triggered = true;
+            }
+        }
+        Ok(triggered)
+    }
+
+    fn cont(&mut self) -> anyhow::Result<()> {
+        // Revisiting an ip only implies a loop when no branch depends on data.
+        let detect_loops = !self.vm.instructions().iter().any(Instruction::is_conditional);
+        let mut seen = HashSet::new();
+        let mut steps = 0;
+        loop {
+            if self.vm.is_terminated() {
+                println!("program terminated");
+                break;
+            }
+
+            let ip = self.vm.state().ip;
+            if detect_loops && !seen.insert(ip) {
+                println!("loop detected: ip {} executed twice", ip);
+                break;
+            }
+
+            if steps == self.fuel {
+                println!("out of fuel after {} steps", steps);
+                break;
+            }
+            steps += 1;
+
+            if self.step()? {
+                break;
+            }
+
+            if self.breakpoints.contains(&self.vm.state().ip) {
+                println!("breakpoint hit at {}", self.vm.state().ip);
+                break;
+            }
+        }
+
+        self.print_state();
+        Ok(())
+    }
+
+    fn parse_ip(&self, arg: Option<&str>) -> anyhow::Result<i32> {
+        let arg = arg.ok_or_else(|| anyhow!("expected an ip"))?;
+        Ok(arg.parse()?)
+    }
+
+    fn command(&mut self, line: &str, original: &[Instruction]) -> anyhow::Result<bool> {
+        let mut args = line.split_whitespace();
+        let cmd = match args.next() {
+            Some(cmd) => cmd,
+            None => return Ok(true),
+        };
+
+        match cmd {
+            "s" | "step" => {
+                let n = args.next().map(str::parse).transpose()?.unwrap_or(1);
+                for _ in 0..n {
+                    if self.step()? {
+                        break;
+                    }
+                }
+                self.print_state();
+            },
+            "c" | "continue" => self.cont()?,
+            "b" | "break" => {
+                let ip = self.parse_ip(args.next())?;
+                self.breakpoints.insert(ip);
+                println!("breakpoint set at {}", ip);
+            },
+            "d" | "delete" => {
+                let ip = self.parse_ip(args.next())?;
+                if !self.breakpoints.remove(&ip) {
+                    Err(anyhow!("no breakpoint at {}", ip))?;
+                }
+            },
+            "w" | "watch" => {
+                if args.next() != Some("acc") {
+                    Err(anyhow!("only acc can be watched"))?;
+                }
+                let comparison = Comparison::parse(args.next().unwrap_or(""))?;
+                let value = args.next().ok_or_else(|| anyhow!("expected a value"))?.parse()?;
+                self.watchpoints.push(Watchpoint{ comparison, value });
+                println!("watchpoint {}: acc {} {}", self.watchpoints.len() - 1, comparison.symbol(), value);
+            },
+            "unwatch" => {
+                let idx: usize = args.next().ok_or_else(|| anyhow!("expected an index"))?.parse()?;
+                if idx >= self.watchpoints.len() {
+                    Err(anyhow!("no watchpoint {}", idx))?;
+                }
+                self.watchpoints.remove(idx);
+            },
+            "i" | "info" => {
+                println!("breakpoints: {:?}", self.breakpoints);
+                for (idx, w) in self.watchpoints.iter().enumerate() {
+                    println!("watchpoint {}: acc {} {}", idx, w.comparison.symbol(), w.value);
+                }
+            },
+            "p" | "print" => self.print_state(),
+            "l" | "list" => {
+                let ip = match args.next() {
+                    Some(ip) => ip.parse()?,
+                    None => self.vm.state().ip,
+                };
+                self.list(ip);
+            },
+            "patch" => {
+                let ip = self.parse_ip(args.next())?;
+                let text = args.collect::<Vec<_>>().join(" ");
+                let inst = match parse_asm(&text)?.as_slice() {
+                    [inst] => *inst,
+                    _ => Err(anyhow!("expected a single instruction"))?,
+                };
+                let old = self.vm.patch(ip as usize, inst)?;
+                println!("{:04} {} -> {}", ip, old, inst);
+            },
+            "record" => match args.next() {
+                Some("on") => self.vm.set_recording(true),
+                Some("off") => self.vm.set_recording(false),
+                _ => Err(anyhow!("expected on or off"))?,
+            },
+            "back" => {
+                let n = args.next().map(str::parse).transpose()?.unwrap_or(1);
+                for _ in 0..n {
+                    self.vm.step_back()?;
+                }
+                self.print_state();
+            },
+            "goto" => {
+                let step = args.next().ok_or_else(|| anyhow!("expected a step"))?.parse()?;
+                if !self.vm.is_recording() {
+                    Err(anyhow!("history is not being recorded"))?;
+                }
+                self.vm.goto_step(step)?;
+                self.print_state();
+            },
+            "lastacc" => match self.vm.last_acc_change() {
+                Some((step, ip)) => println!("acc last changed at step {} by {:04} {}",
+                    step, ip, self.vm.instructions()[ip as usize]),
+                None => println!("acc has not changed in recorded history"),
+            },
+            "save" => {
+                let path = args.next().ok_or_else(|| anyhow!("expected a path"))?;
+                self.vm.snapshot(&SnapshotOptions::default()).save(path)?;
+                println!("snapshot written to {}", path);
+            },
+            "r" | "restart" => self.reset(self.vm.instructions().to_vec())?,
+            "reload" => self.reset(original.to_vec())?,
+            "h" | "help" => println!("{}", HELP),
+            "q" | "quit" => return Ok(false),
+            _ => Err(anyhow!("unknown command: {} (try `help`)", cmd))?,
+        }
+
+        Ok(true)
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = App::new("vmdbg")
+        .arg(Arg::with_name("program")
+            .required_unless("resume"))
+        .arg(Arg::with_name("resume")
+            .long("resume")
+            .takes_value(true)
+            .conflicts_with("program")
+            .help("resume from a saved snapshot"))
+        .arg(Arg::with_name("width")
+            .long("width")
+            .takes_value(true)
+            .possible_values(&["i32", "i64", "i128"])
+            .conflicts_with("resume"))
+        .arg(Arg::with_name("overflow")
+            .long("overflow")
+            .takes_value(true)
+            .possible_values(&["wrapping", "saturating", "checked"])
+            .conflicts_with("resume"))
+        .arg(Arg::with_name("fuel")
+            .short("f")
+            .long("fuel")
+            .takes_value(true)
+            .help("steps `continue` may run before stopping"))
+        .get_matches();
+    let fuel = args.value_of("fuel").map(str::parse).transpose()?.unwrap_or(DEFAULT_FUEL);
+
+    let vm = match args.value_of("resume") {
+        Some(path) => VM::from_snapshot(Snapshot::load(path)?)?,
+        None => {
+            let mut vm = VM::new(bytecode::load(args.value_of("program").unwrap())?.instructions, Default::default());
+            let mut arithmetic = Arithmetic::default();
+            if let Some(width) = args.value_of("width") {
+                arithmetic.width = width.parse()?;
+            }
+            if let Some(overflow) = args.value_of("overflow") {
+                arithmetic.overflow = overflow.parse()?;
+            }
+            vm.set_arithmetic(arithmetic)?;
+            vm
+        },
+    };
+    let program = vm.instructions().to_vec();
+
+    let mut debugger = Debugger{
+        vm,
+        breakpoints: BTreeSet::new(),
+        watchpoints: Vec::new(),
+        fuel,
+    };
+    debugger.print_state();
+
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(vmdbg) ");
+        std::io::stdout().flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+
+        match debugger.command(&line, &program) {
+            Ok(true) => {},
+            Ok(false) => break,
+            Err(e) => println!("error: {}", e),
+        }
+    }
+
+    Ok(())
+}
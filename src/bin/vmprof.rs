@@ -0,0 +1,73 @@
+use clap::{Arg, App};
+use aoc2020::vm::{VM, Arithmetic};
+use aoc2020::bytecode;
+use aoc2020::profile::Profile;
+use aoc2020::optimise::optimise;
+
+fn main() -> anyhow::Result<()> {
+    let args = App::new("vmprof")
+        .arg(Arg::with_name("program")
+            .required(true))
+        .arg(Arg::with_name("fuel")
+            .short("f")
+            .long("fuel")
+            .takes_value(true))
+        .arg(Arg::with_name("top")
+            .short("n")
+            .long("top")
+            .takes_value(true)
+            .default_value("10"))
+        .arg(Arg::with_name("json")
+            .long("json"))
+        .arg(Arg::with_name("annotate")
+            .short("a")
+            .long("annotate"))
+        .arg(Arg::with_name("optimise")
+            .short("O")
+            .long("optimise"))
+        .arg(Arg::with_name("width")
+            .long("width")
+            .takes_value(true)
+            .possible_values(&["i32", "i64", "i128"])
+            .default_value("i32"))
+        .arg(Arg::with_name("overflow")
+            .long("overflow")
+            .takes_value(true)
+            .possible_values(&["wrapping", "saturating", "checked"])
+            .default_value("checked"))
+        .get_matches();
+    let top = args.value_of("top").unwrap().parse()?;
+    let arithmetic = Arithmetic{
+        width: args.value_of("width").unwrap().parse()?,
+        overflow: args.value_of("overflow").unwrap().parse()?,
+    };
+    let mut program = bytecode::load(args.value_of("program").unwrap())?.instructions;
+    if args.is_present("optimise") {
+        program = optimise(&program, arithmetic);
+    }
+
+    let mut vm = VM::new(program.clone(), Default::default());
+    vm.set_arithmetic(arithmetic)?;
+    vm.set_profiling(true);
+    // A failed run is still worth profiling, so report the error last.
+    let result = match args.value_of("fuel") {
+        Some(fuel) => vm.run_with_fuel(fuel.parse()?),
+        None => vm.run(),
+    };
+
+    let profile = Profile::new(&program, vm.profile().unwrap())?;
+    if args.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&profile)?);
+    } else {
+        if let Ok(outcome) = &result {
+            println!("outcome: {:?}\n", outcome);
+        }
+        print!("{}", profile.report(top));
+        if args.is_present("annotate") {
+            print!("\n{}", profile.annotate(&program));
+        }
+    }
+
+    result?;
+    Ok(())
+}
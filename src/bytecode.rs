@@ -0,0 +1,184 @@
+use std::path::{Path, PathBuf};
+use anyhow::anyhow;
+use super::asm::{Program, Symbol, SourceLocation, assemble_file};
+use super::codec::{Encoder, Decoder};
+
+// Layout, with integers as varints (see `codec`):
+//
+//   magic "AOCB", version byte, flags byte
+//   instruction count, instructions
+//   if flags & SYMBOLS: count, then name, kind (0 label, 1 const), value
+//   if flags & SOURCE_MAP: file count, then a present byte and path for each,
+//                          then file, line and column for every instruction
+const MAGIC: &[u8; 4] = b"AOCB";
+const VERSION: u8 = 1;
+const SYMBOLS: u8 = 1;
+const SOURCE_MAP: u8 = 2;
+
+#[derive(Clone, Debug)]
+pub struct EncodeOptions {
+    pub symbols: bool,
+    pub source_map: bool,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> EncodeOptions {
+        EncodeOptions{
+            symbols: true,
+            source_map: true,
+        }
+    }
+}
+
+pub fn is_bytecode(input: &[u8]) -> bool {
+    input.starts_with(MAGIC)
+}
+
+pub fn encode(program: &Program, options: &EncodeOptions) -> Vec<u8> {
+    let mut flags = 0;
+    if options.symbols {
+        flags |= SYMBOLS;
+    }
+    if options.source_map && program.source_map.len() == program.instructions.len() {
+        flags |= SOURCE_MAP;
+    }
+
+    let mut e = Encoder::new();
+    e.bytes(MAGIC);
+    e.u8(VERSION);
+    e.u8(flags);
+    e.instructions(&program.instructions);
+
+    if flags & SYMBOLS != 0 {
+        e.usize(program.symbols.len());
+        for (name, symbol) in program.symbols.iter() {
+            e.str(name);
+            match *symbol {
+                Symbol::Label(addr) => {
+                    e.u8(0);
+                    e.usize(addr);
+                },
+                Symbol::Const(value) => {
+                    e.u8(1);
+                    e.i32(value);
+                },
+            }
+        }
+    }
+
+    if flags & SOURCE_MAP != 0 {
+        e.usize(program.files.len());
+        for file in program.files.iter() {
+            e.bool(file.is_some());
+            if let Some(path) = file {
+                e.str(&path.to_string_lossy());
+            }
+        }
+        for loc in program.source_map.iter() {
+            e.usize(loc.file);
+            e.usize(loc.line);
+            e.usize(loc.column);
+        }
+    }
+
+    e.finish()
+}
+
+pub fn decode(input: &[u8]) -> anyhow::Result<Program> {
+    let mut d = Decoder::new(input);
+    if !is_bytecode(input) {
+        Err(anyhow!("not a bytecode file"))?;
+    }
+    d.bytes(MAGIC.len())?;
+    let version = d.u8()?;
+    if version != VERSION {
+        Err(anyhow!("unsupported bytecode version {}", version))?;
+    }
+    let flags = d.u8()?;
+    if flags & !(SYMBOLS | SOURCE_MAP) != 0 {
+        Err(anyhow!("unknown bytecode flags {:#x}", flags))?;
+    }
+
+    let mut program = Program{
+        instructions: d.instructions()?,
+        ..Default::default()
+    };
+
+    if flags & SYMBOLS != 0 {
+        for _ in 0..d.len(3)? {
+            let name = d.str()?.to_string();
+            let symbol = match d.u8()? {
+                0 => Symbol::Label(d.usize()?),
+                1 => Symbol::Const(d.i32()?),
+                x => Err(anyhow!("invalid symbol kind {}", x))?,
+            };
+            program.symbols.insert(name, symbol);
+        }
+    }
+
+    if flags & SOURCE_MAP != 0 {
+        for _ in 0..d.len(1)? {
+            let file = if d.bool()? {
+                Some(PathBuf::from(d.str()?))
+            } else {
+                None
+            };
+            program.files.push(file);
+        }
+        for _ in 0..program.instructions.len() {
+            let loc = SourceLocation{
+                file: d.usize()?,
+                line: d.usize()?,
+                column: d.usize()?,
+            };
+            if loc.file >= program.files.len() {
+                Err(anyhow!("source map refers to missing file {}", loc.file))?;
+            }
+            program.source_map.push(loc);
+        }
+    }
+
+    if !d.is_empty() {
+        Err(anyhow!("trailing data after bytecode"))?;
+    }
+
+    Ok(program)
+}
+
+// Loads either bytecode or text assembly, told apart by the magic number.
+pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Program> {
+    let contents = std::fs::read(path.as_ref())?;
+    if is_bytecode(&contents) {
+        decode(&contents)
+    } else {
+        assemble_file(path)
+    }
+}
+
+#[test]
+fn test_bytecode() {
+    use super::asm::assemble;
+
+    let program = assemble(include_str!("bin/day8part1.txt")).unwrap();
+    let bytes = encode(&program, &EncodeOptions::default());
+    assert_eq!(decode(&bytes).unwrap(), program);
+    assert_eq!(&bytes[..6], b"AOCB\x01\x03");
+
+    let program = assemble("
+        .const N 3
+    top:
+        mov a, N
+        send 1, a
+        jlt a, 10, top
+    ").unwrap();
+    assert_eq!(program.source_map[1], SourceLocation{ file: 0, line: 5, column: 9 });
+    assert_eq!(decode(&encode(&program, &EncodeOptions::default())).unwrap(), program);
+
+    let stripped = decode(&encode(&program, &EncodeOptions{ symbols: false, source_map: false })).unwrap();
+    assert_eq!(stripped, Program{ instructions: program.instructions.clone(), ..Default::default() });
+
+    let bytes = encode(&program, &EncodeOptions::default());
+    assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+    assert!(decode(b"AOCB\x02\x00\x00").is_err());
+    assert!(decode(b"acc +1").is_err());
+}
@@ -0,0 +1,305 @@
+use std::fmt::Write;
+use super::vm::Instruction;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Target {
+    Block(usize),
+    Exit,
+    OutOfRange(i32),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize,
+    pub successors: Vec<Target>,
+}
+
+impl BasicBlock {
+    pub fn len(&self) -> usize { self.end - self.start }
+
+    pub fn is_empty(&self) -> bool { self.start == self.end }
+}
+
+#[derive(Clone, Debug)]
+pub struct Cfg {
+    blocks: Vec<BasicBlock>,
+    block_of: Vec<usize>,
+}
+
+impl Cfg {
+    pub fn new(instructions: &[Instruction]) -> Cfg {
+        let len = instructions.len();
+        let mut leaders = vec![false; len + 1];
+        if len > 0 {
+            leaders[0] = true;
+        }
+
+        for (ip, inst) in instructions.iter().enumerate() {
+            if let Some(target) = inst.branch_target(ip as i32) {
+                if target >= 0 && (target as usize) < len {
+                    leaders[target as usize] = true;
+                }
+                leaders[ip + 1] = true;
+            }
+        }
+
+        let mut block_of = vec![0; len];
+        let mut starts = Vec::new();
+        for ip in 0..len {
+            if leaders[ip] {
+                starts.push(ip);
+            }
+            block_of[ip] = starts.len() - 1;
+        }
+
+        let target = |ip: i32| {
+            if ip == len as i32 {
+                Target::Exit
+            } else if ip < 0 || ip > len as i32 {
+                Target::OutOfRange(ip)
+            } else {
+                Target::Block(block_of[ip as usize])
+            }
+        };
+
+        let blocks = starts.iter()
+            .copied()
+            .enumerate()
+            .map(|(idx, start)| {
+                let end = starts.get(idx + 1).copied().unwrap_or(len);
+                let last = &instructions[end - 1];
+                let mut successors = Vec::new();
+                if let Some(branch) = last.branch_target(end as i32 - 1) {
+                    successors.push(target(branch));
+                }
+                if last.falls_through() {
+                    successors.push(target(end as i32));
+                }
+                successors.dedup();
+
+                BasicBlock{
+                    start,
+                    end,
+                    successors,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Cfg{
+            blocks,
+            block_of,
+        }
+    }
+
+    pub fn blocks(&self) -> &[BasicBlock] { &self.blocks }
+
+    pub fn block_of(&self, ip: usize) -> Option<usize> { self.block_of.get(ip).copied() }
+
+    pub fn entry(&self) -> Option<usize> {
+        if self.blocks.is_empty() { None } else { Some(0) }
+    }
+
+    pub fn successor_blocks(&self, block: usize) -> impl Iterator<Item=usize> + '_ {
+        self.blocks[block].successors.iter()
+            .filter_map(|t| match t {
+                Target::Block(b) => Some(*b),
+                _ => None,
+            })
+    }
+
+    pub fn predecessors(&self) -> Vec<Vec<usize>> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        for block in 0..self.blocks.len() {
+            for next in self.successor_blocks(block) {
+                predecessors[next].push(block);
+            }
+        }
+        predecessors
+    }
+
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut stack = self.entry().into_iter().collect::<Vec<_>>();
+        while let Some(block) = stack.pop() {
+            if reachable[block] {
+                continue
+            }
+
+            reachable[block] = true;
+            stack.extend(self.successor_blocks(block));
+        }
+        reachable
+    }
+
+    pub fn unreachable_blocks(&self) -> Vec<usize> {
+        self.reachable().iter()
+            .enumerate()
+            .filter(|(_, r)| !**r)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    // Tarjan's algorithm, written iteratively so that very long programs do
+    // not overflow the stack. Components are returned in reverse topological
+    // order.
+    pub fn strongly_connected_components(&self) -> Vec<Vec<usize>> {
+        let n = self.blocks.len();
+        let mut index = vec![usize::MAX; n];
+        let mut low_link = vec![0; n];
+        let mut on_stack = vec![false; n];
+        let mut stack = Vec::new();
+        let mut next_index = 0;
+        let mut components = Vec::new();
+
+        for root in 0..n {
+            if index[root] != usize::MAX {
+                continue
+            }
+
+            let mut work = vec![(root, 0)];
+            while let Some((block, child)) = work.pop() {
+                if child == 0 {
+                    index[block] = next_index;
+                    low_link[block] = next_index;
+                    next_index += 1;
+                    stack.push(block);
+                    on_stack[block] = true;
+                }
+
+                let successors = self.successor_blocks(block).collect::<Vec<_>>();
+                if let Some(&next) = successors.get(child) {
+                    work.push((block, child + 1));
+                    if index[next] == usize::MAX {
+                        work.push((next, 0));
+                    } else if on_stack[next] {
+                        low_link[block] = low_link[block].min(index[next]);
+                    }
+                    continue
+                }
+
+                if low_link[block] == index[block] {
+                    let mut component = Vec::new();
+                    loop {
+                        let b = stack.pop().unwrap();
+                        on_stack[b] = false;
+                        component.push(b);
+                        if b == block {
+                            break
+                        }
+                    }
+                    component.sort_unstable();
+                    components.push(component);
+                }
+
+                if let Some(&(parent, _)) = work.last() {
+                    low_link[parent] = low_link[parent].min(low_link[block]);
+                }
+            }
+        }
+
+        components
+    }
+
+    // Components which can execute forever: either several blocks, or a
+    // single block that jumps to itself.
+    pub fn loops(&self) -> Vec<Vec<usize>> {
+        self.strongly_connected_components()
+            .into_iter()
+            .filter(|c| c.len() > 1 || self.successor_blocks(c[0]).any(|b| b == c[0]))
+            .collect()
+    }
+
+    pub fn to_dot(&self, instructions: &[Instruction]) -> String {
+        let reachable = self.reachable();
+        let mut in_loop = vec![false; self.blocks.len()];
+        for block in self.loops().into_iter().flatten() {
+            in_loop[block] = true;
+        }
+
+        let mut out = String::new();
+        writeln!(out, "digraph cfg {{").unwrap();
+        writeln!(out, "    node [shape=box, fontname=monospace];").unwrap();
+        writeln!(out, "    entry [shape=oval];").unwrap();
+        writeln!(out, "    exit [shape=oval];").unwrap();
+
+        for (idx, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for (ip, inst) in (block.start..).zip(&instructions[block.start..block.end]) {
+                write!(label, "{:04} {}\\l", ip, inst).unwrap();
+            }
+
+            let style = if !reachable[idx] {
+                ", style=dashed, color=grey"
+            } else if in_loop[idx] {
+                ", color=red"
+            } else {
+                ""
+            };
+            writeln!(out, "    b{} [label=\"{}\"{}];", idx, label, style).unwrap();
+        }
+
+        if let Some(entry) = self.entry() {
+            writeln!(out, "    entry -> b{};", entry).unwrap();
+        } else {
+            writeln!(out, "    entry -> exit;").unwrap();
+        }
+
+        for (idx, block) in self.blocks.iter().enumerate() {
+            for target in block.successors.iter() {
+                match target {
+                    Target::Block(b) => writeln!(out, "    b{} -> b{};", idx, b).unwrap(),
+                    Target::Exit => writeln!(out, "    b{} -> exit;", idx).unwrap(),
+                    Target::OutOfRange(ip) => {
+                        // DOT IDs can't contain '-', so negative ips are written `oor_m4`.
+                        let id = if *ip < 0 { format!("oor_m{}", -(*ip as i64)) } else { format!("oor{}", ip) };
+                        writeln!(out, "    {} [label=\"out of range {}\", shape=oval, color=red];", id, ip).unwrap();
+                        writeln!(out, "    b{} -> {};", idx, id).unwrap();
+                    },
+                }
+            }
+        }
+
+        writeln!(out, "}}").unwrap();
+        out
+    }
+}
+
+#[test]
+fn test_cfg() {
+    use Instruction::*;
+
+    let program = vec![
+        Nop(0), Acc(1), Jmp(4), Acc(3), Jmp(-3),
+        Acc(-99), Acc(1), Jmp(-4), Acc(6),
+    ];
+    let cfg = Cfg::new(&program);
+    let blocks = cfg.blocks().iter()
+        .map(|b| (b.start, b.end, b.successors.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(blocks, vec![
+        (0, 1, vec![Target::Block(1)]),
+        (1, 3, vec![Target::Block(4)]),
+        (3, 5, vec![Target::Block(1)]),
+        (5, 6, vec![Target::Block(4)]),
+        (6, 8, vec![Target::Block(2)]),
+        (8, 9, vec![Target::Exit]),
+    ]);
+    assert_eq!(cfg.unreachable_blocks(), vec![3, 5]);
+    assert_eq!(cfg.loops(), vec![vec![1, 2, 4]]);
+
+    let cfg = Cfg::new(&[Jmp(0), Jmp(-5)]);
+    assert_eq!(cfg.loops(), vec![vec![0]]);
+    assert_eq!(cfg.blocks()[1].successors, vec![Target::OutOfRange(-4)]);
+
+    // Every node ID in the DOT output is a plain identifier.
+    let dot = Cfg::new(&[Jmp(0), Jmp(-5), Jmp(3)]).to_dot(&[Jmp(0), Jmp(-5), Jmp(3)]);
+    assert!(dot.contains("b1 -> oor_m4;") && dot.contains("b2 -> oor5;"));
+    for line in dot.lines().skip(1).filter(|l| !l.starts_with('}') && !l.contains("node [")) {
+        let ids = line.trim().split(" [").next().unwrap().trim_end_matches(';');
+        for id in ids.split(" -> ") {
+            assert!(id.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_'), "{}", line);
+            assert!(id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'), "{}", line);
+        }
+    }
+}
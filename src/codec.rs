@@ -0,0 +1,298 @@
+use anyhow::anyhow;
+use super::vm::{Instruction, Operand, Register, State, Arithmetic, Width, Overflow};
+
+// Compact binary encoding shared by the snapshot and bytecode formats.
+// Integers are LEB128 varints, with signed values zigzag-encoded first.
+
+#[derive(Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder { Encoder::default() }
+
+    pub fn finish(self) -> Vec<u8> { self.buf }
+
+    pub fn bytes(&mut self, b: &[u8]) {
+        self.buf.extend_from_slice(b);
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub fn bool(&mut self, v: bool) {
+        self.u8(v as u8);
+    }
+
+    pub fn uvarint(&mut self, mut v: u64) {
+        loop {
+            let byte = (v & 0x7f) as u8;
+            v >>= 7;
+            if v == 0 {
+                self.buf.push(byte);
+                break
+            }
+            self.buf.push(byte | 0x80);
+        }
+    }
+
+    pub fn ivarint(&mut self, v: i64) {
+        self.uvarint(((v << 1) ^ (v >> 63)) as u64);
+    }
+
+    pub fn i128(&mut self, v: i128) {
+        let mut v = ((v << 1) ^ (v >> 127)) as u128;
+        loop {
+            let byte = (v & 0x7f) as u8;
+            v >>= 7;
+            if v == 0 {
+                self.buf.push(byte);
+                break
+            }
+            self.buf.push(byte | 0x80);
+        }
+    }
+
+    pub fn usize(&mut self, v: usize) {
+        self.uvarint(v as u64);
+    }
+
+    pub fn i32(&mut self, v: i32) {
+        self.ivarint(v as i64);
+    }
+
+    pub fn str(&mut self, s: &str) {
+        self.usize(s.len());
+        self.bytes(s.as_bytes());
+    }
+
+    pub fn register(&mut self, r: Register) {
+        let idx = Register::ALL.iter().position(|x| *x == r).unwrap();
+        self.u8(idx as u8);
+    }
+
+    pub fn operand(&mut self, operand: Operand) {
+        match operand {
+            Operand::Imm(x) => {
+                self.u8(0);
+                self.i32(x);
+            },
+            Operand::Reg(r) => {
+                self.u8(1);
+                self.register(r);
+            },
+        }
+    }
+
+    pub fn instruction(&mut self, inst: &Instruction) {
+        match *inst {
+            Instruction::Acc(x) => { self.u8(0); self.i32(x); },
+            Instruction::Jmp(x) => { self.u8(1); self.i32(x); },
+            Instruction::Nop(x) => { self.u8(2); self.i32(x); },
+            Instruction::Mov(r, x) => { self.u8(3); self.register(r); self.operand(x); },
+            Instruction::Add(r, x) => { self.u8(4); self.register(r); self.operand(x); },
+            Instruction::Mul(r, x) => { self.u8(5); self.register(r); self.operand(x); },
+            Instruction::Mod(r, x) => { self.u8(6); self.register(r); self.operand(x); },
+            Instruction::Load(r, x) => { self.u8(7); self.register(r); self.operand(x); },
+            Instruction::Store(a, x) => { self.u8(8); self.operand(a); self.operand(x); },
+            Instruction::Jz(x, o) => { self.u8(9); self.operand(x); self.i32(o); },
+            Instruction::Jnz(x, o) => { self.u8(10); self.operand(x); self.i32(o); },
+            Instruction::Jlt(x, y, o) => { self.u8(11); self.operand(x); self.operand(y); self.i32(o); },
+            Instruction::In(r, p) => { self.u8(12); self.register(r); self.i32(p); },
+            Instruction::Out(p, x) => { self.u8(13); self.i32(p); self.operand(x); },
+            Instruction::Send(c, x) => { self.u8(14); self.i32(c); self.operand(x); },
+            Instruction::Recv(r, c) => { self.u8(15); self.register(r); self.i32(c); },
+        }
+    }
+
+    pub fn instructions(&mut self, instructions: &[Instruction]) {
+        self.usize(instructions.len());
+        for inst in instructions.iter() {
+            self.instruction(inst);
+        }
+    }
+
+    pub fn arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.u8(match arithmetic.width {
+            Width::I32 => 0,
+            Width::I64 => 1,
+            Width::I128 => 2,
+        });
+        self.u8(match arithmetic.overflow {
+            Overflow::Wrapping => 0,
+            Overflow::Saturating => 1,
+            Overflow::Checked => 2,
+        });
+    }
+
+    pub fn state(&mut self, state: &State) {
+        self.i32(state.ip);
+        self.i128(state.acc);
+        for r in state.regs.iter().copied() {
+            self.i32(r);
+        }
+    }
+}
+
+pub struct Decoder<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(input: &'a [u8]) -> Decoder<'a> {
+        Decoder{ input }
+    }
+
+    pub fn is_empty(&self) -> bool { self.input.is_empty() }
+
+    pub fn bytes(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if self.input.len() < len {
+            Err(anyhow!("unexpected end of input"))?;
+        }
+        let (head, tail) = self.input.split_at(len);
+        self.input = tail;
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> anyhow::Result<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            x => Err(anyhow!("invalid bool {}", x)),
+        }
+    }
+
+    pub fn uvarint(&mut self) -> anyhow::Result<u64> {
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            v |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(v)
+            }
+        }
+        Err(anyhow!("varint too long"))
+    }
+
+    pub fn ivarint(&mut self) -> anyhow::Result<i64> {
+        let v = self.uvarint()?;
+        Ok(((v >> 1) as i64) ^ -((v & 1) as i64))
+    }
+
+    pub fn i128(&mut self) -> anyhow::Result<i128> {
+        let mut v = 0u128;
+        for shift in (0..128).step_by(7) {
+            let byte = self.u8()?;
+            v |= ((byte & 0x7f) as u128) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(((v >> 1) as i128) ^ -((v & 1) as i128))
+            }
+        }
+        Err(anyhow!("varint too long"))
+    }
+
+    pub fn usize(&mut self) -> anyhow::Result<usize> {
+        let v = self.uvarint()?;
+        if v > usize::MAX as u64 {
+            Err(anyhow!("length {} too large", v))?;
+        }
+        Ok(v as usize)
+    }
+
+    // A length prefix for items at least `min_size` bytes each, checked
+    // against the remaining input so corrupt data cannot force huge
+    // allocations.
+    pub fn len(&mut self, min_size: usize) -> anyhow::Result<usize> {
+        let len = self.usize()?;
+        if len.saturating_mul(min_size) > self.input.len() {
+            Err(anyhow!("length {} exceeds remaining input", len))?;
+        }
+        Ok(len)
+    }
+
+    pub fn i32(&mut self) -> anyhow::Result<i32> {
+        let v = self.ivarint()?;
+        if v < i32::MIN as i64 || v > i32::MAX as i64 {
+            Err(anyhow!("value {} out of range", v))?;
+        }
+        Ok(v as i32)
+    }
+
+    pub fn str(&mut self) -> anyhow::Result<&'a str> {
+        let len = self.len(1)?;
+        Ok(std::str::from_utf8(self.bytes(len)?)?)
+    }
+
+    pub fn register(&mut self) -> anyhow::Result<Register> {
+        let idx = self.u8()? as usize;
+        Register::ALL.get(idx).copied()
+            .ok_or_else(|| anyhow!("invalid register {}", idx))
+    }
+
+    pub fn operand(&mut self) -> anyhow::Result<Operand> {
+        match self.u8()? {
+            0 => Ok(Operand::Imm(self.i32()?)),
+            1 => Ok(Operand::Reg(self.register()?)),
+            x => Err(anyhow!("invalid operand tag {}", x)),
+        }
+    }
+
+    pub fn instruction(&mut self) -> anyhow::Result<Instruction> {
+        Ok(match self.u8()? {
+            0 => Instruction::Acc(self.i32()?),
+            1 => Instruction::Jmp(self.i32()?),
+            2 => Instruction::Nop(self.i32()?),
+            3 => Instruction::Mov(self.register()?, self.operand()?),
+            4 => Instruction::Add(self.register()?, self.operand()?),
+            5 => Instruction::Mul(self.register()?, self.operand()?),
+            6 => Instruction::Mod(self.register()?, self.operand()?),
+            7 => Instruction::Load(self.register()?, self.operand()?),
+            8 => Instruction::Store(self.operand()?, self.operand()?),
+            9 => Instruction::Jz(self.operand()?, self.i32()?),
+            10 => Instruction::Jnz(self.operand()?, self.i32()?),
+            11 => Instruction::Jlt(self.operand()?, self.operand()?, self.i32()?),
+            12 => Instruction::In(self.register()?, self.i32()?),
+            13 => Instruction::Out(self.i32()?, self.operand()?),
+            14 => Instruction::Send(self.i32()?, self.operand()?),
+            15 => Instruction::Recv(self.register()?, self.i32()?),
+            x => Err(anyhow!("invalid opcode {}", x))?,
+        })
+    }
+
+    pub fn instructions(&mut self) -> anyhow::Result<Vec<Instruction>> {
+        let len = self.len(2)?;
+        (0..len).map(|_| self.instruction()).collect()
+    }
+
+    pub fn arithmetic(&mut self) -> anyhow::Result<Arithmetic> {
+        let width = match self.u8()? {
+            0 => Width::I32,
+            1 => Width::I64,
+            2 => Width::I128,
+            x => Err(anyhow!("invalid width {}", x))?,
+        };
+        let overflow = match self.u8()? {
+            0 => Overflow::Wrapping,
+            1 => Overflow::Saturating,
+            2 => Overflow::Checked,
+            x => Err(anyhow!("invalid overflow policy {}", x))?,
+        };
+        Ok(Arithmetic{ width, overflow })
+    }
+
+    pub fn state(&mut self) -> anyhow::Result<State> {
+        let ip = self.i32()?;
+        let acc = self.i128()?;
+        let mut regs = [0; 4];
+        for r in regs.iter_mut() {
+            *r = self.i32()?;
+        }
+        Ok(State{ ip, acc, regs })
+    }
+}
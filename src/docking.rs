@@ -0,0 +1,450 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use anyhow::anyhow;
+use nom::{
+    IResult,
+    character::complete::{space0, digit1, alphanumeric1, char},
+    sequence::tuple,
+    InputTakeAtPosition,
+};
+use super::asm::{NomError, parse_lines};
+
+pub const ADDRESS_BITS: u32 = 36;
+const ADDRESS_MASK: u64 = (1 << ADDRESS_BITS) - 1;
+
+// A 36 bit mask such as `1XX0`, read most significant bit first. Bits which
+// are neither set nor cleared are floating.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Mask {
+    set: u64,
+    clear: u64,
+}
+
+impl Mask {
+    pub fn new(set: u64, clear: u64) -> anyhow::Result<Mask> {
+        if set & clear != 0 {
+            Err(anyhow!("mask bits {:#x} are both set and cleared", set & clear))?;
+        }
+        if (set | clear) & !ADDRESS_MASK != 0 {
+            Err(anyhow!("mask is wider than {} bits", ADDRESS_BITS))?;
+        }
+        Ok(Mask{ set, clear })
+    }
+
+    pub fn set(&self) -> u64 { self.set }
+
+    pub fn clear(&self) -> u64 { self.clear }
+
+    pub fn floating(&self) -> u64 {
+        !(self.set | self.clear) & ADDRESS_MASK
+    }
+
+    // The version 1 decoder masks values.
+    pub fn apply(&self, value: u64) -> u64 {
+        (value & !self.clear) | self.set
+    }
+
+    // The version 2 decoder writes every address matching the mask, where
+    // cleared bits keep the address's bit and floating bits take both values.
+    pub fn decode_address(&self, addr: u64) -> Pattern {
+        Pattern::new(self.set | (addr & self.clear), self.floating())
+    }
+}
+
+impl FromStr for Mask {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Mask> {
+        if s.is_empty() || s.len() > ADDRESS_BITS as usize {
+            Err(anyhow!("mask must have 1 to {} bits, not {}", ADDRESS_BITS, s.len()))?;
+        }
+
+        let mut set = 0;
+        let mut clear = 0;
+        for c in s.chars() {
+            set <<= 1;
+            clear <<= 1;
+            match c {
+                '0' => clear |= 1,
+                '1' => set |= 1,
+                'X' => {},
+                c => Err(anyhow!("invalid mask bit {:?}", c))?,
+            }
+        }
+        Mask::new(set, clear)
+    }
+}
+
+impl fmt::Display for Mask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for bit in (0..ADDRESS_BITS).rev() {
+            let c = match (self.set >> bit & 1, self.clear >> bit & 1) {
+                (1, _) => '1',
+                (_, 1) => '0',
+                _ => 'X',
+            };
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+// A set of addresses, which have the bits of `addr` outside `floating` and
+// any bits within it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pattern {
+    addr: u64,
+    floating: u64,
+}
+
+impl Pattern {
+    pub fn new(addr: u64, floating: u64) -> Pattern {
+        let floating = floating & ADDRESS_MASK;
+        Pattern{
+            addr: addr & ADDRESS_MASK & !floating,
+            floating,
+        }
+    }
+
+    // The lowest address in the pattern.
+    pub fn addr(&self) -> u64 { self.addr }
+
+    pub fn floating(&self) -> u64 { self.floating }
+
+    // The number of addresses, at most 2^36.
+    pub fn size(&self) -> u64 {
+        1 << self.floating.count_ones()
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        addr & !self.floating == self.addr
+    }
+
+    pub fn overlaps(&self, other: &Pattern) -> bool {
+        (self.addr ^ other.addr) & !self.floating & !other.floating == 0
+    }
+
+    // Splits the addresses in `self` but not in `other` into disjoint
+    // patterns, fixing one of our floating bits which `other` fixes at a time.
+    pub fn subtract(&self, other: &Pattern) -> Vec<Pattern> {
+        if !self.overlaps(other) {
+            return vec![*self];
+        }
+
+        let mut pieces = Vec::new();
+        let mut rest = *self;
+        let mut bits = self.floating & !other.floating;
+        while bits != 0 {
+            let bit = bits & bits.wrapping_neg();
+            bits &= !bit;
+            rest.floating &= !bit;
+            pieces.push(Pattern{ addr: rest.addr | (!other.addr & bit), floating: rest.floating });
+            rest.addr |= other.addr & bit;
+        }
+        pieces
+    }
+
+    // Every address in the pattern, in increasing order.
+    pub fn addresses(&self) -> impl Iterator<Item = u64> {
+        let Pattern{ addr, floating } = *self;
+        let mut subset = Some(0u64);
+
+        std::iter::from_fn(move || {
+            let bits = subset?;
+            subset = if bits == floating {
+                None
+            } else {
+                Some(bits.wrapping_sub(floating) & floating)
+            };
+            Some(addr | bits)
+        })
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for bit in (0..ADDRESS_BITS).rev() {
+            let c = match (self.floating >> bit & 1, self.addr >> bit & 1) {
+                (1, _) => 'X',
+                (_, 1) => '1',
+                _ => '0',
+            };
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+// Memory written through patterns rather than single addresses, so that a
+// write to 2^30 addresses is one entry. Writes to a single address are kept
+// by address, so a program without floating bits costs no more than a map.
+// No two entries overlap: a write first removes its addresses from any
+// entry it overlaps.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FloatingMemory {
+    exact: BTreeMap<u64, u64>,
+    patterns: BTreeMap<Pattern, u64>,
+}
+
+impl FloatingMemory {
+    pub fn new() -> FloatingMemory {
+        Default::default()
+    }
+
+    pub fn write(&mut self, pattern: Pattern, value: u64) {
+        let overlapping = self.patterns.keys()
+            .filter(|p| **p != pattern && p.overlaps(&pattern))
+            .copied()
+            .collect::<Vec<_>>();
+
+        for old in overlapping {
+            let v = self.patterns.remove(&old).unwrap();
+            for piece in old.subtract(&pattern) {
+                self.patterns.insert(piece, v);
+            }
+        }
+
+        if pattern.floating == 0 {
+            self.exact.insert(pattern.addr, value);
+        } else {
+            let covered = self.exact.range(pattern.addr..=pattern.addr | pattern.floating)
+                .map(|(addr, _)| *addr)
+                .filter(|addr| pattern.contains(*addr))
+                .collect::<Vec<_>>();
+            for addr in covered {
+                self.exact.remove(&addr);
+            }
+            self.patterns.insert(pattern, value);
+        }
+    }
+
+    pub fn get(&self, addr: u64) -> u64 {
+        match self.exact.get(&addr) {
+            Some(v) => *v,
+            None => self.patterns.iter()
+                .find(|(p, _)| p.contains(addr))
+                .map_or(0, |(_, v)| *v),
+        }
+    }
+
+    // The disjoint patterns written, ordered by their lowest address.
+    pub fn iter(&self) -> impl Iterator<Item = (Pattern, u64)> {
+        let mut entries = self.exact.iter()
+            .map(|(addr, v)| (Pattern::new(*addr, 0), *v))
+            .chain(self.patterns.iter().map(|(p, v)| (*p, *v)))
+            .collect::<Vec<_>>();
+        entries.sort_unstable();
+        entries.into_iter()
+    }
+
+    // The sum of every address, as 2^36 addresses of 36 bit values can
+    // exceed a u64.
+    pub fn sum(&self) -> u128 {
+        let exact = self.exact.values().map(|v| *v as u128).sum::<u128>();
+        exact + self.patterns.iter()
+            .map(|(p, v)| p.size() as u128 * *v as u128)
+            .sum::<u128>()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Mask(Mask),
+    Set {
+        addr: u64,
+        value: u64,
+    },
+}
+
+fn parse_mask(input: &str) -> IResult<&str, Mask, NomError> {
+    let (rest, bits) = input.split_at_position_complete(|c| c != '0' && c != '1' && c != 'X')?;
+    let mask = bits.parse().map_err(|e| NomError::fail_at(input, e))?;
+    Ok((rest, mask))
+}
+
+fn parse_number(input: &str) -> IResult<&str, u64, NomError> {
+    let (rest, digits) = digit1(input)?;
+    let v = digits.parse().map_err(|e| NomError::fail_at(input, e))?;
+    Ok((rest, v))
+}
+
+// Addresses wider than the decoder would alias lower ones once masked.
+fn parse_address(input: &str) -> IResult<&str, u64, NomError> {
+    let (rest, addr) = parse_number(input)?;
+    if addr & !ADDRESS_MASK != 0 {
+        Err(NomError::fail_at(input, anyhow!("address {} is wider than {} bits", addr, ADDRESS_BITS)))?;
+    }
+    Ok((rest, addr))
+}
+
+impl Command {
+    pub fn parse(input: &str) -> IResult<&str, Command, NomError> {
+        let (rest, cmd) = alphanumeric1(input)?;
+        let (rest, _) = space0(rest)?;
+
+        match cmd {
+            "mem" => {
+                let (rest, (_, _, addr, _, _)) = tuple((char('['), space0, parse_address, space0, char(']')))(rest)?;
+                let (rest, (_, _, _, value)) = tuple((space0, char('='), space0, parse_number))(rest)?;
+                Ok((rest, Command::Set{ addr, value }))
+            },
+            "mask" => {
+                let (rest, (_, _, mask)) = tuple((char('='), space0, parse_mask))(rest)?;
+                Ok((rest, Command::Mask(mask)))
+            },
+            _ => Err(NomError::fail_at(input, anyhow!("unexpected command {:?}", cmd))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Program {
+    pub commands: Vec<Command>,
+}
+
+impl FromStr for Program {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Program> {
+        Ok(Program{ commands: parse_lines(s, Command::parse)? })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Decoder {
+    V1,
+    V2,
+}
+
+impl FromStr for Decoder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Decoder> {
+        match s {
+            "v1" | "1" => Ok(Decoder::V1),
+            "v2" | "2" => Ok(Decoder::V2),
+            _ => Err(anyhow!("unknown decoder {:?}, expected v1 or v2", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DockingMachine {
+    decoder: Decoder,
+    mask: Mask,
+    memory: FloatingMemory,
+}
+
+impl DockingMachine {
+    pub fn new(decoder: Decoder) -> DockingMachine {
+        DockingMachine{
+            decoder,
+            mask: Mask::default(),
+            memory: FloatingMemory::new(),
+        }
+    }
+
+    pub fn decoder(&self) -> Decoder { self.decoder }
+
+    pub fn mask(&self) -> Mask { self.mask }
+
+    pub fn memory(&self) -> &FloatingMemory { &self.memory }
+
+    pub fn execute(&mut self, command: &Command) {
+        match *command {
+            Command::Mask(mask) => self.mask = mask,
+            Command::Set{ addr, value } => match self.decoder {
+                Decoder::V1 => self.memory.write(Pattern::new(addr, 0), self.mask.apply(value)),
+                Decoder::V2 => self.memory.write(self.mask.decode_address(addr), value),
+            },
+        }
+    }
+
+    pub fn run(&mut self, program: &Program) {
+        for command in program.commands.iter() {
+            self.execute(command);
+        }
+    }
+
+    pub fn sum(&self) -> u128 {
+        self.memory.sum()
+    }
+}
+
+#[test]
+fn test_docking() {
+    let mask: Mask = "XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0X".parse().unwrap();
+    assert_eq!((mask.set(), mask.clear()), (64, 2));
+    assert_eq!(mask.to_string(), "XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0X");
+    assert_eq!(mask.apply(11), 73);
+    assert!("1X2".parse::<Mask>().is_err());
+    assert!(Mask::new(1, 1).is_err());
+
+    let program: Program = "\
+mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0X
+mem[8] = 11
+mem[7] = 101
+mem[8] = 0".parse().unwrap();
+    let mut machine = DockingMachine::new(Decoder::V1);
+    machine.run(&program);
+    assert_eq!(machine.sum(), 165);
+
+    let mask: Mask = "000000000000000000000000000000X1001X".parse().unwrap();
+    assert_eq!(mask.decode_address(42).addresses().collect::<Vec<_>>(), vec![26, 27, 58, 59]);
+
+    let program: Program = "\
+mask = 000000000000000000000000000000X1001X
+mem[42] = 100
+mask = 00000000000000000000000000000000X0XX
+mem[26] = 1".parse().unwrap();
+    let mut machine = DockingMachine::new(Decoder::V2);
+    machine.run(&program);
+    assert_eq!(machine.sum(), 208);
+
+    let mut machine = DockingMachine::new(Decoder::V2);
+    machine.run(&"mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX\nmem[0] = 5\nmask = 0XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX\nmem[0] = 1".parse().unwrap());
+    assert_eq!(machine.sum(), 5 * (1 << 35) + (1 << 35));
+
+    // Overlapping writes with 34 floating bits, which enumeration can't handle.
+    let mut memory = FloatingMemory::new();
+    memory.write(Pattern::new(0, ADDRESS_MASK >> 2), 1);
+    memory.write(Pattern::new(1 << 33, ADDRESS_MASK >> 3), 2);
+    memory.write(Pattern::new(5, ADDRESS_MASK & !0xff), 3);
+    memory.write(Pattern::new(6, 0), 4);
+    assert_eq!(memory.sum(), ((1 << 33) - (1 << 25) - 1) + ((1 << 33) - (1 << 25)) * 2 + (1 << 28) * 3 + 4);
+    assert_eq!((memory.get(5), memory.get(6), memory.get(1 << 33), memory.get(7)), (3, 4, 2, 1));
+    assert!(memory.iter().all(|(p, _)| memory.iter().filter(|(q, _)| p.overlaps(q)).count() == 1));
+    memory.write(Pattern::new(7, 0), 5);
+    memory.write(Pattern::new(4, 3), 6);
+    assert_eq!((memory.get(5), memory.get(6), memory.get(7), memory.get(8)), (6, 6, 6, 1));
+    assert!(memory.iter().all(|(p, _)| memory.iter().filter(|(q, _)| p.overlaps(q)).count() == 1));
+
+    assert!("mask = 1X\nmem[1] = x".parse::<Program>().is_err());
+    assert!("mem[68719476736] = 5".parse::<Program>().is_err());
+    assert!("mem[68719476735] = 5".parse::<Program>().is_ok());
+    assert!("mask = 12".parse::<Program>().is_err());
+}
+
+// Against enumerating every address, with few floating bits.
+#[test]
+fn test_docking_differential() {
+    use super::testutil::Rng;
+
+    let mut rng = Rng::new(1);
+    let mut next = || rng.next_u64() >> 28;
+    for _ in 0..50 {
+        let mut memory = FloatingMemory::new();
+        let mut expected = BTreeMap::new();
+        for _ in 0..20 {
+            let pattern = Pattern::new(next() & 0xff, next() & next() & next() & ADDRESS_MASK);
+            let value = next() & 0xffff;
+            memory.write(pattern, value);
+            for addr in pattern.addresses() {
+                expected.insert(addr, value);
+            }
+        }
+        assert_eq!(memory.sum(), expected.values().map(|v| *v as u128).sum());
+        assert!(expected.iter().all(|(addr, v)| memory.get(*addr) == *v));
+    }
+}
@@ -0,0 +1,1016 @@
+use std::fmt;
+use std::str::FromStr;
+use serde::{de, ser, Serialize, Deserialize};
+use serde::de::{Error as _, IntoDeserializer, Visitor};
+
+// A serde format for records written as `key:value` fields separated by
+// whitespace, with records separated by blank lines:
+//
+//   name:alice tags:a,b
+//   colour:blu
+//
+//   name:bob tags:c tags:d
+//
+// A struct or map is one block, and a sequence of them is several. Field
+// values are strings, numbers, bools, unit enum variants by name, options
+// (a missing field is `None`), or sequences, written as a comma separated
+// list, as repeated keys, or both.
+//
+// Nested structs are only supported through `#[serde(flatten)]`, and only
+// partly: serde reads flattened fields without knowing their types, so each
+// value arrives as a string, or a sequence of strings if its key repeats.
+// Strings, unit enums and types which deserialize from a string work there,
+// but numbers and bools don't, so `struct I { n: u32 }` can't be flattened.
+// Guessing types instead would read `pid:087499704` as a number, losing the
+// leading zero, and break string fields whose values look like numbers.
+
+// A 1-based line and column, counting chars.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    fn of(source: &str, offset: usize) -> Position {
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+        Position{
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+// Errors from reading input have a position: a malformed field is reported
+// where it starts, a bad value at the first value of its key, and a missing
+// field at the end of its block. Errors from writing have none.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    message: String,
+    position: Option<Position>,
+}
+
+impl Error {
+    pub fn message(&self) -> &str { &self.message }
+
+    pub fn position(&self) -> Option<Position> { self.position }
+
+    // Places the error at `offset` in `source`, unless it already has a
+    // more precise position.
+    fn at(mut self, source: &str, offset: usize) -> Error {
+        if self.position.is_none() {
+            self.position = Some(Position::of(source, offset));
+        }
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some(p) => write!(f, "{}:{}: {}", p.line, p.column, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error{
+            message: msg.to_string(),
+            position: None,
+        }
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error{
+            message: msg.to_string(),
+            position: None,
+        }
+    }
+}
+
+pub fn split_blocks(input: &str) -> impl Iterator<Item=&str> {
+    input.split("\n\n")
+}
+
+// Reads a struct or map from one block, or a sequence of them from blocks
+// separated by blank lines.
+pub fn from_str<'de, T: Deserialize<'de>>(input: &'de str) -> Result<T, Error> {
+    T::deserialize(Deserializer{ source: input, input, offset: 0 })
+}
+
+// Reads `block`, one of the blocks of `source` from `split_blocks`, giving
+// error positions within `source` rather than the block.
+pub fn from_block<'de, T: Deserialize<'de>>(source: &'de str, block: &'de str) -> Result<T, Error> {
+    let offset = (block.as_ptr() as usize).checked_sub(source.as_ptr() as usize)
+        .filter(|offset| source.len().checked_sub(*offset).is_some_and(|left| block.len() <= left))
+        .ok_or_else(|| Error::custom("block is not a slice of source"))?;
+    T::deserialize(Deserializer{ source, input: block, offset })
+}
+
+struct Deserializer<'de> {
+    // The whole input, for positions.
+    source: &'de str,
+    input: &'de str,
+    // Where `input` starts in `source`.
+    offset: usize,
+}
+
+impl<'de> Deserializer<'de> {
+    fn block(&self) -> Result<BlockAccess<'de>, Error> {
+        let mut fields: Vec<Field<'de>> = Vec::new();
+        let mut rest = self.input.trim_start();
+        while !rest.is_empty() {
+            let offset = self.offset + self.input.len() - rest.len();
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let (field, after) = rest.split_at(end);
+            let idx = field.find(':')
+                .filter(|idx| *idx > 0)
+                .ok_or_else(|| Error::custom(format!("expected key:value, found {:?}", field))
+                    .at(self.source, offset))?;
+            let (key, value) = (&field[..idx], &field[idx + 1..]);
+
+            match fields.iter_mut().find(|f| f.key == key) {
+                Some(f) => f.values.push(value),
+                None => fields.push(Field{ key, offset, values: vec![value] }),
+            }
+            rest = after.trim_start();
+        }
+
+        Ok(BlockAccess{
+            source: self.source,
+            end: self.offset + self.input.trim_end().len(),
+            fields: fields.into_iter(),
+            value: None,
+        })
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        let block = self.block()?;
+        let (source, end) = (block.source, block.end);
+        visitor.visit_map(block).map_err(|e| e.at(source, end))
+    }
+
+    fn deserialize_struct<V>(self, _name: &'static str, _fields: &'static [&'static str], visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        let source = self.source;
+        let mut offset = self.offset;
+        let blocks = split_blocks(self.input)
+            .map(|input| {
+                let block = Deserializer{ source, input, offset };
+                offset += input.len() + "\n\n".len();
+                block
+            })
+            .filter(|b| !b.input.trim().is_empty());
+        de::value::SeqDeserializer::new(blocks).deserialize_any(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct tuple tuple_struct enum identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Deserializer<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+struct Field<'de> {
+    key: &'de str,
+    // Where the key first appears.
+    offset: usize,
+    values: Vec<&'de str>,
+}
+
+// The fields of a block in the order their keys first appear, with the
+// values of repeated keys together.
+struct BlockAccess<'de> {
+    source: &'de str,
+    // Where the block ends, for errors about the block as a whole.
+    end: usize,
+    fields: std::vec::IntoIter<Field<'de>>,
+    value: Option<Field<'de>>,
+}
+
+impl<'de> de::MapAccess<'de> for BlockAccess<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<<K as de::DeserializeSeed<'de>>::Value>, Self::Error> where
+        K: de::DeserializeSeed<'de> {
+        let field = match self.fields.next() {
+            Some(field) => field,
+            None => return Ok(None),
+        };
+
+        let (key, offset) = (field.key, field.offset);
+        self.value = Some(field);
+        seed.deserialize(de::value::BorrowedStrDeserializer::new(key))
+            .map(Some)
+            .map_err(|e: Error| e.at(self.source, offset))
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<<V as de::DeserializeSeed<'de>>::Value, Self::Error> where
+        V: de::DeserializeSeed<'de> {
+        let Field{ key, offset, values } = self.value.take()
+            .ok_or_else(|| Error::custom("called next_value without next_key"))?;
+        // The first value starts after the key and its ':'.
+        let offset = offset + key.len() + 1;
+        seed.deserialize(FieldDeserializer{ key, values })
+            .map_err(|e| Error::custom(format!("{}: {}", key, e.message)).at(self.source, offset))
+    }
+}
+
+// Reads the values of one key, of which there are several if the key was
+// repeated. Only sequences accept more than one.
+struct FieldDeserializer<'de> {
+    key: &'de str,
+    values: Vec<&'de str>,
+}
+
+impl<'de> FieldDeserializer<'de> {
+    fn single(&self) -> Result<&'de str, Error> {
+        match self.values[..] {
+            [value] => Ok(value),
+            _ => Err(Error::custom("expected one value, found a repeated key")),
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for FieldDeserializer<'de> {
+    type Error = Error;
+
+    // Without a type to guide it, such as within a flattened struct, a value
+    // is a string, or a sequence of strings if its key was repeated.
+    fn deserialize_any<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        if self.values.len() > 1 {
+            self.deserialize_seq(visitor)
+        } else {
+            visitor.visit_borrowed_str(self.single()?)
+        }
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_bool(FromStr::from_str(self.single()?).map_err(Error::custom)?)
+    }
+
+    fn deserialize_i8<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_i8(FromStr::from_str(self.single()?).map_err(Error::custom)?)
+    }
+
+    fn deserialize_i16<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_i16(FromStr::from_str(self.single()?).map_err(Error::custom)?)
+    }
+
+    fn deserialize_i32<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_i32(FromStr::from_str(self.single()?).map_err(Error::custom)?)
+    }
+
+    fn deserialize_i64<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_i64(FromStr::from_str(self.single()?).map_err(Error::custom)?)
+    }
+
+    fn deserialize_u8<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_u8(FromStr::from_str(self.single()?).map_err(Error::custom)?)
+    }
+
+    fn deserialize_u16<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_u16(FromStr::from_str(self.single()?).map_err(Error::custom)?)
+    }
+
+    fn deserialize_u32<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_u32(FromStr::from_str(self.single()?).map_err(Error::custom)?)
+    }
+
+    fn deserialize_u64<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_u64(FromStr::from_str(self.single()?).map_err(Error::custom)?)
+    }
+
+    fn deserialize_f32<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_f32(FromStr::from_str(self.single()?).map_err(Error::custom)?)
+    }
+
+    fn deserialize_f64<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_f64(FromStr::from_str(self.single()?).map_err(Error::custom)?)
+    }
+
+    fn deserialize_char<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        let mut chars = self.single()?.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => visitor.visit_char(c),
+            _ => Err(Error::custom("char must be length 1")),
+        }
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_borrowed_str(self.single()?)
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_string(String::from(self.single()?))
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_borrowed_bytes(self.single()?.as_bytes())
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_byte_buf(self.single()?.as_bytes().to_vec())
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // The items of a sequence are every value of the key, each split on
+    // commas, so `a:1,2 a:3` gives `[1, 2, 3]`.
+    fn deserialize_seq<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        let key = self.key;
+        let items = self.values.into_iter()
+            .flat_map(|v| v.split(','))
+            .filter(|v| !v.is_empty())
+            .map(|v| FieldDeserializer{ key, values: vec![v] });
+        de::value::SeqDeserializer::new(items).deserialize_any(visitor)
+    }
+
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(self, _name: &'static str, _len: usize, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V>(self, _visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        Err(Error::custom("map not supported"))
+    }
+
+    fn deserialize_struct<V>(self, _name: &'static str, _fields: &'static [&'static str], _visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        Err(Error::custom("nested struct not supported, use #[serde(flatten)]"))
+    }
+
+    // Only unit variants, written by name.
+    fn deserialize_enum<V>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_enum(de::value::BorrowedStrDeserializer::new(self.single()?))
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_borrowed_str(self.single()?)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
+        V: Visitor<'de> {
+        visitor.visit_unit()
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for FieldDeserializer<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+// Writes a struct or map as one block of `key:value` fields, or a sequence
+// of them as blocks separated by blank lines. `None` values are left out, and
+// sequences are written as repeated keys, so an empty one is left out too.
+// A record in a sequence must have a field left to write.
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String, Error> {
+    let mut serializer = Serializer{
+        output: String::new(),
+        fields: 0,
+        in_block: false,
+        in_seq: false,
+        key: None,
+    };
+    value.serialize(&mut serializer)?;
+    Ok(serializer.output)
+}
+
+pub struct Serializer {
+    output: String,
+    // Fields written in the current block.
+    fields: usize,
+    in_block: bool,
+    in_seq: bool,
+    // The key of a map entry waiting for its value.
+    key: Option<String>,
+}
+
+impl Serializer {
+    fn not_block<T>(&self, what: &str) -> Result<T, Error> {
+        Err(ser::Error::custom(format!("can't write {} as a block, expected a struct or map", what)))
+    }
+
+    fn begin_block(&mut self) -> Result<(), Error> {
+        if self.in_block {
+            return self.not_block("a nested struct or map");
+        }
+        self.in_block = true;
+        self.fields = 0;
+        Ok(())
+    }
+
+    fn write_field<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<(), Error> {
+        if key.is_empty() || key.contains(|c: char| c == ':' || c.is_whitespace()) {
+            return Err(ser::Error::custom(format!("invalid key {:?}", key)));
+        }
+
+        for value in value.serialize(FieldSerializer{ in_seq: false })? {
+            if self.fields > 0 {
+                self.output.push(' ');
+            }
+            self.output.push_str(key);
+            self.output.push(':');
+            self.output.push_str(&value);
+            self.fields += 1;
+        }
+        Ok(())
+    }
+}
+
+impl ser::Serializer for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Self;
+    type SerializeTuple = ser::Impossible<(), Error>;
+    type SerializeTupleStruct = ser::Impossible<(), Error>;
+    type SerializeTupleVariant = ser::Impossible<(), Error>;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = ser::Impossible<(), Error>;
+
+    fn serialize_bool(self, _v: bool) -> Result<(), Self::Error> {
+        self.not_block("a bool")
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), Self::Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), Self::Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), Self::Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<(), Self::Error> {
+        self.not_block("a number")
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Self::Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), Self::Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), Self::Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<(), Self::Error> {
+        self.not_block("a number")
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<(), Self::Error> {
+        self.not_block("a number")
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<(), Self::Error> {
+        self.not_block("a number")
+    }
+
+    fn serialize_char(self, _v: char) -> Result<(), Self::Error> {
+        self.not_block("a char")
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<(), Self::Error> {
+        self.not_block("a string")
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(), Self::Error> {
+        self.not_block("bytes")
+    }
+
+    fn serialize_none(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _variant_index: u32, _variant: &'static str) -> Result<(), Self::Error> {
+        self.not_block("an enum")
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<(), Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _variant_index: u32, _variant: &'static str, _value: &T) -> Result<(), Self::Error> {
+        self.not_block("an enum")
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        if self.in_seq || self.in_block {
+            return self.not_block("a nested sequence");
+        }
+        self.in_seq = true;
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        self.not_block("a tuple")
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        self.not_block("a tuple")
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _variant_index: u32, _variant: &'static str, _len: usize) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        self.not_block("an enum")
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        self.begin_block()?;
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct, Self::Error> {
+        self.begin_block()?;
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _variant_index: u32, _variant: &'static str, _len: usize) -> Result<Self::SerializeStructVariant, Self::Error> {
+        self.not_block("an enum")
+    }
+}
+
+impl ser::SerializeSeq for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    // Empty blocks are skipped when reading, so a record with no fields to
+    // write would be lost.
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        if !self.output.is_empty() {
+            self.output.push_str("\n\n");
+        }
+        self.fields = 0;
+        value.serialize(&mut **self)?;
+        if self.fields == 0 {
+            return Err(ser::Error::custom("can't write an empty record in a sequence, it would be dropped"));
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        self.in_seq = false;
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Self::Error> {
+        let mut keys = key.serialize(FieldSerializer{ in_seq: false })?;
+        if keys.len() != 1 {
+            return Err(ser::Error::custom("map keys must be a single value"));
+        }
+        self.key = keys.pop();
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        let key = self.key.take()
+            .ok_or_else(|| ser::Error::custom("called serialize_value without serialize_key"))?;
+        self.write_field(&key, value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        self.in_block = false;
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error> {
+        self.write_field(key, value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        self.in_block = false;
+        Ok(())
+    }
+}
+
+// Writes a field's values, which can't be empty or contain whitespace.
+// `None` and unit values give none, so the field is left out, and a sequence
+// gives one per item, which are written with repeated keys.
+struct FieldSerializer {
+    in_seq: bool,
+}
+
+impl FieldSerializer {
+    fn value<T: fmt::Display>(v: T) -> Result<Vec<String>, Error> {
+        let v = v.to_string();
+        if v.is_empty() || v.contains(char::is_whitespace) {
+            return Err(ser::Error::custom(format!("value {:?} can't be empty or contain whitespace", v)));
+        }
+        Ok(vec![v])
+    }
+
+    fn unsupported<T>(what: &str) -> Result<T, Error> {
+        Err(ser::Error::custom(format!("{} not supported in a field", what)))
+    }
+}
+
+impl ser::Serializer for FieldSerializer {
+    type Ok = Vec<String>;
+    type Error = Error;
+    type SerializeSeq = FieldSeq;
+    type SerializeTuple = FieldSeq;
+    type SerializeTupleStruct = ser::Impossible<Vec<String>, Error>;
+    type SerializeTupleVariant = ser::Impossible<Vec<String>, Error>;
+    type SerializeMap = ser::Impossible<Vec<String>, Error>;
+    type SerializeStruct = ser::Impossible<Vec<String>, Error>;
+    type SerializeStructVariant = ser::Impossible<Vec<String>, Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        FieldSerializer::value(v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        FieldSerializer::value(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        FieldSerializer::value(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        FieldSerializer::value(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        FieldSerializer::value(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
+        FieldSerializer::value(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        FieldSerializer::value(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        FieldSerializer::value(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
+        FieldSerializer::value(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
+        FieldSerializer::value(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
+        FieldSerializer::value(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+        FieldSerializer::value(v)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        FieldSerializer::value(v)
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        FieldSerializer::unsupported("bytes")
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Ok(Vec::new())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        Ok(Vec::new())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(Vec::new())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _variant_index: u32, variant: &'static str) -> Result<Self::Ok, Self::Error> {
+        FieldSerializer::value(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _variant_index: u32, _variant: &'static str, _value: &T) -> Result<Self::Ok, Self::Error> {
+        FieldSerializer::unsupported("enum")
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        if self.in_seq {
+            return FieldSerializer::unsupported("nested seq");
+        }
+        Ok(FieldSeq{ values: Vec::new() })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        FieldSerializer::unsupported("tuple")
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _variant_index: u32, _variant: &'static str, _len: usize) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        FieldSerializer::unsupported("enum")
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        FieldSerializer::unsupported("map")
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct, Self::Error> {
+        FieldSerializer::unsupported("struct")
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _variant_index: u32, _variant: &'static str, _len: usize) -> Result<Self::SerializeStructVariant, Self::Error> {
+        FieldSerializer::unsupported("enum")
+    }
+}
+
+// Items are read back by splitting on commas, so they can't contain one.
+struct FieldSeq {
+    values: Vec<String>,
+}
+
+impl ser::SerializeSeq for FieldSeq {
+    type Ok = Vec<String>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        for v in value.serialize(FieldSerializer{ in_seq: true })? {
+            if v.contains(',') {
+                return Err(ser::Error::custom(format!("sequence item {:?} can't contain a comma", v)));
+            }
+            self.values.push(v);
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<Vec<String>, Self::Error> {
+        Ok(self.values)
+    }
+}
+
+impl ser::SerializeTuple for FieldSeq {
+    type Ok = Vec<String>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Vec<String>, Self::Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+#[test]
+fn test_kv() {
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Colour {
+        Red,
+        Blue,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Record {
+        name: String,
+        #[serde(default)]
+        tags: Vec<String>,
+        colour: Colour,
+        size: Option<u32>,
+        #[serde(flatten)]
+        extra: BTreeMap<String, String>,
+    }
+
+    let input = "\
+name:alice tags:a,b colour:red
+tags:c size:3 note:hi
+
+colour:blue name:bob";
+    let records = from_str::<Vec<Record>>(input).unwrap();
+    assert_eq!(records[0].tags, vec!["a", "b", "c"]);
+    assert_eq!((&records[0].colour, records[0].size), (&Colour::Red, Some(3)));
+    assert_eq!(records[0].extra.get("note").map(String::as_str), Some("hi"));
+    assert_eq!(records[1], Record{
+        name: "bob".to_string(),
+        tags: vec![],
+        colour: Colour::Blue,
+        size: None,
+        extra: BTreeMap::new(),
+    });
+
+    // Sequences are written as repeated keys, and read back either way.
+    let output = to_string(&records).unwrap();
+    assert_eq!(output, "name:alice tags:a tags:b tags:c colour:red size:3 note:hi\n\nname:bob colour:blue");
+    assert_eq!(from_str::<Vec<Record>>(&output).unwrap(), records);
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Owner {
+        owner: String,
+        email: String,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Pet {
+        pet: String,
+        #[serde(flatten)]
+        owner: Owner,
+    }
+    let pet = from_str::<Pet>("email:a@b.c pet:cat owner:alice").unwrap();
+    assert_eq!((pet.pet.as_str(), pet.owner.owner.as_str(), pet.owner.email.as_str()), ("cat", "alice", "a@b.c"));
+
+    // Flattened fields arrive untyped, so only string-like ones can be read.
+    #[derive(Debug, Deserialize)]
+    struct Count {
+        #[allow(dead_code)]
+        n: u32,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Counted {
+        #[serde(flatten)]
+        #[allow(dead_code)]
+        count: Count,
+    }
+    assert!(from_str::<Counted>("n:3").is_err());
+
+    let error = |input| {
+        let e = from_str::<Vec<Record>>(input).unwrap_err();
+        let p = e.position().unwrap();
+        (p.line, p.column, e.message().to_string())
+    };
+    assert_eq!(error("name:a colour:pink"), (1, 15, "colour: unknown variant `pink`, expected `red` or `blue`".to_string()));
+    assert_eq!(error("name:a colour:red\n\nname:b\n"), (3, 7, "missing field `colour`".to_string()));
+    assert_eq!(error("name:a\n oops colour:red"), (2, 2, "expected key:value, found \"oops\"".to_string()));
+    assert_eq!(error("name:a colour:red name:b"), (1, 6, "name: expected one value, found a repeated key".to_string()));
+    assert_eq!(error("name:a colour:red size:x"), (1, 24, "size: invalid digit found in string".to_string()));
+    assert_eq!(error("name:a colour:red :x"), (1, 19, "expected key:value, found \":x\"".to_string()));
+    assert_eq!(error("colour:red\n\nname:a colour:red\n\nname:b colour:red"), (1, 11, "missing field `name`".to_string()));
+
+    // Blocks read on their own still report positions within the source.
+    let source = "name:a colour:red\n\nname:b colour:pink";
+    let block = split_blocks(source).nth(1).unwrap();
+    let e = from_block::<Record>(source, block).unwrap_err();
+    assert_eq!(e.position(), Some(Position{ line: 3, column: 15 }));
+    assert!(from_block::<Record>(source, "name:b colour:red").is_err());
+    assert!(from_block::<Record>(block, source).is_err());
+
+    let mut fields = BTreeMap::new();
+    fields.insert("b".to_string(), "2".to_string());
+    fields.insert("a".to_string(), "x".to_string());
+    assert_eq!(to_string(&fields).unwrap(), "a:x b:2");
+    assert_eq!(from_str::<BTreeMap<String, String>>("a:x\nb:2\n").unwrap(), fields);
+
+    #[derive(Serialize)]
+    struct Nested {
+        inner: BTreeMap<String, String>,
+    }
+    assert!(to_string(&Nested{ inner: fields.clone() }).is_err());
+    assert!(to_string(&vec![vec![1]]).is_err());
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Maybe {
+        x: Option<String>,
+    }
+    let empty = Maybe{ x: None };
+    assert!(to_string(&vec![Maybe{ x: Some("y".to_string()) }, Maybe{ x: None }]).is_err());
+    assert_eq!(from_str::<Maybe>(&to_string(&empty).unwrap()).unwrap(), empty);
+
+    // Items that wouldn't read back as themselves are refused.
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Tags {
+        tags: Vec<String>,
+    }
+    let tags = |tags: &[&str]| Tags{ tags: tags.iter().map(|t| t.to_string()).collect() };
+    let output = to_string(&tags(&["a", "b"])).unwrap();
+    assert_eq!(output, "tags:a tags:b");
+    assert_eq!(from_str::<Tags>(&output).unwrap(), tags(&["a", "b"]));
+    assert!(to_string(&tags(&["a,b"])).is_err());
+    assert!(to_string(&tags(&["a", ""])).is_err());
+    fields.insert("c".to_string(), "two words".to_string());
+    assert!(to_string(&fields).is_err());
+    assert!(to_string(&1).is_err());
+    assert_eq!(to_string(&1).unwrap_err().position(), None);
+}
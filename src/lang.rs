@@ -0,0 +1,686 @@
+use std::collections::BTreeMap;
+use nom::{
+    IResult,
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{char, digit1, multispace1, not_line_ending},
+    combinator::{opt, recognize},
+    multi::many0,
+    sequence::{pair, preceded},
+};
+use anyhow::anyhow;
+use super::vm::{self, Instruction, Operand, Register};
+use super::asm::{self, NomError, Diagnostics, DisassembleOptions};
+
+// A small structured language:
+//
+//   # comments run to the end of the line
+//   n = 10;
+//   while n > 0 {
+//       if n * 2 > 10 { acc = acc + n; } else { acc = acc - 1; }
+//       n = n - 1;
+//   }
+//   out acc;
+//
+// Variables are integers which start at zero. `acc` is the VM's accumulator,
+// so the program's result can be read from the final state, and `out`
+// writes to port 0. Conditions compare two expressions with < <= > >= == !=,
+// or test a single expression for being nonzero.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Num(i32),
+    Var(String),
+    Neg(Box<Expr>),
+    Bin(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Cmp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Cond {
+    Cmp(Cmp, Expr, Expr),
+    NonZero(Expr),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Stmt {
+    Assign(String, Expr),
+    While(Cond, Vec<Stmt>),
+    If(Cond, Vec<Stmt>, Vec<Stmt>),
+    Out(Expr),
+}
+
+const KEYWORDS: &[&str] = &["while", "if", "else", "out"];
+
+type PResult<'a, T> = IResult<&'a str, T, NomError>;
+
+fn sp(input: &str) -> PResult<'_, ()> {
+    let (input, _) = many0(alt((multispace1, recognize(pair(char('#'), not_line_ending)))))(input)?;
+    Ok((input, ()))
+}
+
+// Runs `parser`, turning a failure to match into an error at the first
+// non-space character saying what was expected.
+fn expect<'a, O, F>(mut parser: F, what: &'static str) -> impl FnMut(&'a str) -> PResult<'a, O>
+where F: FnMut(&'a str) -> PResult<'a, O> {
+    move |input| match parser(input) {
+        Err(nom::Err::Error(_)) => {
+            let (at, _) = sp(input)?;
+            Err(NomError::fail_at(at, anyhow!("expected {}", what)))
+        },
+        r => r,
+    }
+}
+
+fn symbol<'a>(s: &'static str) -> impl FnMut(&'a str) -> PResult<'a, &'a str> {
+    preceded(sp, tag(s))
+}
+
+fn identifier(input: &str) -> PResult<'_, &str> {
+    let (rest, name) = preceded(sp, asm::parse_identifier)(input)?;
+    if KEYWORDS.contains(&name) {
+        return Err(NomError::fail_at(input.trim_start(), anyhow!("`{}` is a keyword", name)));
+    }
+    Ok((rest, name))
+}
+
+fn number(input: &str) -> PResult<'_, i32> {
+    let (rest, digits) = preceded(sp, digit1)(input)?;
+    let value = digits.parse().map_err(|e| NomError::fail_at(digits, e))?;
+    Ok((rest, value))
+}
+
+fn factor(input: &str) -> PResult<'_, Expr> {
+    if let Ok((rest, _)) = symbol("(")(input) {
+        let (rest, e) = expr(rest)?;
+        let (rest, _) = expect(symbol(")"), "`)`")(rest)?;
+        return Ok((rest, e));
+    }
+    if let Ok((rest, _)) = symbol("-")(input) {
+        let (rest, e) = expect(factor, "an expression")(rest)?;
+        return Ok((rest, Expr::Neg(Box::new(e))));
+    }
+    if let Ok((rest, n)) = number(input) {
+        return Ok((rest, Expr::Num(n)));
+    }
+    let (rest, name) = identifier(input)?;
+    Ok((rest, Expr::Var(name.to_string())))
+}
+
+fn term(input: &str) -> PResult<'_, Expr> {
+    let (mut input, mut lhs) = factor(input)?;
+    while let Ok((rest, _)) = symbol("*")(input) {
+        let (rest, rhs) = expect(factor, "an expression")(rest)?;
+        lhs = Expr::Bin(BinOp::Mul, Box::new(lhs), Box::new(rhs));
+        input = rest;
+    }
+    Ok((input, lhs))
+}
+
+fn expr(input: &str) -> PResult<'_, Expr> {
+    let (mut input, mut lhs) = term(input)?;
+    while let Ok((rest, op)) = alt((symbol("+"), symbol("-")))(input) {
+        let op = if op == "+" { BinOp::Add } else { BinOp::Sub };
+        let (rest, rhs) = expect(term, "an expression")(rest)?;
+        lhs = Expr::Bin(op, Box::new(lhs), Box::new(rhs));
+        input = rest;
+    }
+    Ok((input, lhs))
+}
+
+fn cond(input: &str) -> PResult<'_, Cond> {
+    let (rest, lhs) = expect(expr, "a condition")(input)?;
+    let (rest, op) = opt(alt((
+        symbol("<="), symbol(">="), symbol("=="), symbol("!="), symbol("<"), symbol(">"))))(rest)?;
+    let op = match op {
+        Some("<") => Cmp::Lt,
+        Some("<=") => Cmp::Le,
+        Some(">") => Cmp::Gt,
+        Some(">=") => Cmp::Ge,
+        Some("==") => Cmp::Eq,
+        Some(_) => Cmp::Ne,
+        None => return Ok((rest, Cond::NonZero(lhs))),
+    };
+    let (rest, rhs) = expect(expr, "an expression")(rest)?;
+    Ok((rest, Cond::Cmp(op, lhs, rhs)))
+}
+
+fn block(input: &str) -> PResult<'_, Vec<Stmt>> {
+    let (mut input, _) = expect(symbol("{"), "`{`")(input)?;
+    let mut stmts = Vec::new();
+    loop {
+        if let Ok((rest, _)) = symbol("}")(input) {
+            return Ok((rest, stmts));
+        }
+        let (rest, s) = expect(stmt, "a statement or `}`")(input)?;
+        stmts.push(s);
+        input = rest;
+    }
+}
+
+fn stmt(input: &str) -> PResult<'_, Stmt> {
+    let (rest, word) = preceded(sp, asm::parse_identifier)(input)?;
+    match word {
+        "while" => {
+            let (rest, c) = cond(rest)?;
+            let (rest, body) = block(rest)?;
+            Ok((rest, Stmt::While(c, body)))
+        },
+        "if" => {
+            let (rest, c) = cond(rest)?;
+            let (rest, then) = block(rest)?;
+            let (rest, otherwise) = match preceded(sp, asm::parse_identifier)(rest) {
+                Ok((rest, "else")) => match preceded(sp, asm::parse_identifier)(rest) {
+                    Ok((_, "if")) => stmt(rest).map(|(rest, s)| (rest, vec![s]))?,
+                    _ => block(rest)?,
+                },
+                _ => (rest, Vec::new()),
+            };
+            Ok((rest, Stmt::If(c, then, otherwise)))
+        },
+        "out" => {
+            let (rest, e) = expect(expr, "an expression")(rest)?;
+            let (rest, _) = expect(symbol(";"), "`;`")(rest)?;
+            Ok((rest, Stmt::Out(e)))
+        },
+        "else" => Err(NomError::fail_at(input.trim_start(), anyhow!("`else` without `if`"))),
+        name => {
+            let (rest, _) = expect(symbol("="), "`=`")(rest)?;
+            let (rest, e) = expect(expr, "an expression")(rest)?;
+            let (rest, _) = expect(symbol(";"), "`;`")(rest)?;
+            Ok((rest, Stmt::Assign(name.to_string(), e)))
+        },
+    }
+}
+
+pub fn parse(source: &str) -> Result<Vec<Stmt>, Diagnostics> {
+    let mut input = source;
+    let mut stmts = Vec::new();
+    loop {
+        let result = sp(input).and_then(|(rest, _)| {
+            if rest.is_empty() {
+                return Ok((rest, None));
+            }
+            expect(stmt, "a statement")(rest).map(|(rest, s)| (rest, Some(s)))
+        });
+
+        match result {
+            Ok((_, None)) => return Ok(stmts),
+            Ok((rest, Some(s))) => {
+                stmts.push(s);
+                input = rest;
+            },
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => return Err(Diagnostics(vec![e.into_diagnostic(source)])),
+            Err(nom::Err::Incomplete(_)) => unreachable!("complete parsers only"),
+        }
+    }
+}
+
+enum Item {
+    Inst(Instruction),
+    Branch(Instruction, usize),
+    Label(usize),
+}
+
+// Variables live in memory from address 0, followed by temporaries for
+// expressions too deep to evaluate in registers. Expressions are evaluated
+// into register a, using b as scratch. `acc` updates by a constant use the
+// original `acc` instruction.
+struct Compiler {
+    items: Vec<Item>,
+    vars: BTreeMap<String, i32>,
+    temps: i32,
+    labels: usize,
+}
+
+fn collect_vars(stmts: &[Stmt], vars: &mut BTreeMap<String, i32>) {
+    fn visit_expr(e: &Expr, vars: &mut BTreeMap<String, i32>) {
+        match e {
+            Expr::Num(_) => {},
+            Expr::Var(name) => add(name, vars),
+            Expr::Neg(e) => visit_expr(e, vars),
+            Expr::Bin(_, l, r) => {
+                visit_expr(l, vars);
+                visit_expr(r, vars);
+            },
+        }
+    }
+
+    fn visit_cond(c: &Cond, vars: &mut BTreeMap<String, i32>) {
+        match c {
+            Cond::Cmp(_, l, r) => {
+                visit_expr(l, vars);
+                visit_expr(r, vars);
+            },
+            Cond::NonZero(e) => visit_expr(e, vars),
+        }
+    }
+
+    fn add(name: &str, vars: &mut BTreeMap<String, i32>) {
+        if name != "acc" && !vars.contains_key(name) {
+            let addr = vars.len() as i32;
+            vars.insert(name.to_string(), addr);
+        }
+    }
+
+    for s in stmts {
+        match s {
+            Stmt::Assign(name, e) => {
+                add(name, vars);
+                visit_expr(e, vars);
+            },
+            Stmt::While(c, body) => {
+                visit_cond(c, vars);
+                collect_vars(body, vars);
+            },
+            Stmt::If(c, then, otherwise) => {
+                visit_cond(c, vars);
+                collect_vars(then, vars);
+                collect_vars(otherwise, vars);
+            },
+            Stmt::Out(e) => visit_expr(e, vars),
+        }
+    }
+}
+
+const A: Operand = Operand::Reg(Register::A);
+const B: Operand = Operand::Reg(Register::B);
+
+impl Compiler {
+    fn emit(&mut self, inst: Instruction) {
+        self.items.push(Item::Inst(inst));
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels - 1
+    }
+
+    fn temp(&mut self, depth: i32) -> Operand {
+        self.temps = self.temps.max(depth + 1);
+        Operand::Imm(self.vars.len() as i32 + depth)
+    }
+
+    // An operand for `e` which needs no evaluation, loading variables into b.
+    fn simple(&mut self, e: &Expr) -> Option<Operand> {
+        match e {
+            Expr::Num(n) => Some(Operand::Imm(*n)),
+            Expr::Var(name) if name == "acc" => Some(Operand::Reg(Register::Acc)),
+            Expr::Var(name) => {
+                self.emit(Instruction::Load(Register::B, Operand::Imm(self.vars[name])));
+                Some(B)
+            },
+            _ => None,
+        }
+    }
+
+    // Evaluates `l` into a and `r` into the returned operand.
+    fn pair(&mut self, l: &Expr, r: &Expr, depth: i32) -> Operand {
+        self.expr(l, depth);
+        if let Some(operand) = self.simple(r) {
+            return operand;
+        }
+
+        let temp = self.temp(depth);
+        self.emit(Instruction::Store(temp, A));
+        self.expr(r, depth + 1);
+        self.emit(Instruction::Mov(Register::B, A));
+        self.emit(Instruction::Load(Register::A, temp));
+        B
+    }
+
+    fn expr(&mut self, e: &Expr, depth: i32) {
+        match e {
+            Expr::Num(n) => self.emit(Instruction::Mov(Register::A, Operand::Imm(*n))),
+            Expr::Var(name) if name == "acc" => self.emit(Instruction::Mov(Register::A, Operand::Reg(Register::Acc))),
+            Expr::Var(name) => self.emit(Instruction::Load(Register::A, Operand::Imm(self.vars[name]))),
+            Expr::Neg(e) => {
+                self.expr(e, depth);
+                self.emit(Instruction::Mul(Register::A, Operand::Imm(-1)));
+            },
+            Expr::Bin(op, l, r) => {
+                let rhs = self.pair(l, r, depth);
+                match (op, rhs) {
+                    (BinOp::Add, x) => self.emit(Instruction::Add(Register::A, x)),
+                    (BinOp::Mul, x) => self.emit(Instruction::Mul(Register::A, x)),
+                    (BinOp::Sub, Operand::Imm(n)) if n.checked_neg().is_some() => {
+                        self.emit(Instruction::Add(Register::A, Operand::Imm(-n)))
+                    },
+                    (BinOp::Sub, x) => {
+                        if x != B {
+                            self.emit(Instruction::Mov(Register::B, x));
+                        }
+                        self.emit(Instruction::Mul(Register::B, Operand::Imm(-1)));
+                        self.emit(Instruction::Add(Register::A, B));
+                    },
+                }
+            },
+        }
+    }
+
+    // Branches to `target` when `c` is false, and falls through otherwise.
+    fn unless(&mut self, c: &Cond, target: usize) {
+        let (op, a, b) = match c {
+            Cond::NonZero(e) => {
+                self.expr(e, 0);
+                self.items.push(Item::Branch(Instruction::Jz(A, 0), target));
+                return
+            },
+            Cond::Cmp(op, l, r) => {
+                let b = self.pair(l, r, 0);
+                (*op, A, b)
+            },
+        };
+
+        let jump = Item::Branch(Instruction::Jmp(0), target);
+        match op {
+            Cmp::Lt => {
+                self.emit(Instruction::Jlt(a, b, 2));
+                self.items.push(jump);
+            },
+            Cmp::Ge => self.items.push(Item::Branch(Instruction::Jlt(a, b, 0), target)),
+            Cmp::Gt => {
+                self.emit(Instruction::Jlt(b, a, 2));
+                self.items.push(jump);
+            },
+            Cmp::Le => self.items.push(Item::Branch(Instruction::Jlt(b, a, 0), target)),
+            Cmp::Eq => {
+                self.items.push(Item::Branch(Instruction::Jlt(a, b, 0), target));
+                self.items.push(Item::Branch(Instruction::Jlt(b, a, 0), target));
+            },
+            Cmp::Ne => {
+                self.emit(Instruction::Jlt(a, b, 3));
+                self.emit(Instruction::Jlt(b, a, 2));
+                self.items.push(jump);
+            },
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for s in stmts {
+            self.stmt(s);
+        }
+    }
+
+    fn stmt(&mut self, s: &Stmt) {
+        match s {
+            Stmt::Assign(name, Expr::Bin(op, l, r)) if name == "acc" && **l == Expr::Var("acc".to_string()) => {
+                match (op, &**r) {
+                    (BinOp::Add, Expr::Num(n)) => self.emit(Instruction::Acc(*n)),
+                    (BinOp::Sub, Expr::Num(n)) if n.checked_neg().is_some() => self.emit(Instruction::Acc(-*n)),
+                    (op, r) => {
+                        self.expr(r, 0);
+                        match op {
+                            BinOp::Add => self.emit(Instruction::Add(Register::Acc, A)),
+                            BinOp::Sub => {
+                                self.emit(Instruction::Mul(Register::A, Operand::Imm(-1)));
+                                self.emit(Instruction::Add(Register::Acc, A));
+                            },
+                            BinOp::Mul => self.emit(Instruction::Mul(Register::Acc, A)),
+                        }
+                    },
+                }
+            },
+            Stmt::Assign(name, e) if name == "acc" => {
+                self.expr(e, 0);
+                self.emit(Instruction::Mov(Register::Acc, A));
+            },
+            Stmt::Assign(name, Expr::Num(n)) => {
+                self.emit(Instruction::Store(Operand::Imm(self.vars[name]), Operand::Imm(*n)));
+            },
+            Stmt::Assign(name, e) => {
+                self.expr(e, 0);
+                self.emit(Instruction::Store(Operand::Imm(self.vars[name]), A));
+            },
+            Stmt::While(c, body) => {
+                let (top, end) = (self.label(), self.label());
+                self.items.push(Item::Label(top));
+                self.unless(c, end);
+                self.stmts(body);
+                self.items.push(Item::Branch(Instruction::Jmp(0), top));
+                self.items.push(Item::Label(end));
+            },
+            Stmt::If(c, then, otherwise) => {
+                let (other, end) = (self.label(), self.label());
+                self.unless(c, other);
+                self.stmts(then);
+                if !otherwise.is_empty() {
+                    self.items.push(Item::Branch(Instruction::Jmp(0), end));
+                }
+                self.items.push(Item::Label(other));
+                self.stmts(otherwise);
+                self.items.push(Item::Label(end));
+            },
+            Stmt::Out(e) => {
+                self.expr(e, 0);
+                self.emit(Instruction::Out(0, A));
+            },
+        }
+    }
+
+    fn finish(self) -> Vec<Instruction> {
+        let mut positions = vec![0; self.labels];
+        let mut ip = 0;
+        for item in self.items.iter() {
+            match item {
+                Item::Label(label) => positions[*label] = ip,
+                _ => ip += 1,
+            }
+        }
+
+        let mut out = Vec::with_capacity(ip as usize);
+        for item in self.items.iter() {
+            match item {
+                Item::Inst(inst) => out.push(*inst),
+                Item::Branch(inst, label) => {
+                    let ip = out.len() as i32;
+                    out.push(inst.with_branch_offset(positions[*label] - ip));
+                },
+                Item::Label(_) => {},
+            }
+        }
+        out
+    }
+}
+
+pub fn compile_stmts(stmts: &[Stmt]) -> anyhow::Result<Vec<Instruction>> {
+    let mut vars = BTreeMap::new();
+    collect_vars(stmts, &mut vars);
+
+    let mut compiler = Compiler{
+        items: Vec::new(),
+        vars,
+        temps: 0,
+        labels: 0,
+    };
+    compiler.stmts(stmts);
+
+    let memory = compiler.vars.len() + compiler.temps as usize;
+    if memory > vm::DEFAULT_MEMORY_SIZE {
+        Err(anyhow!("program needs {} memory cells, more than the {} available", memory, vm::DEFAULT_MEMORY_SIZE))?;
+    }
+    Ok(compiler.finish())
+}
+
+pub fn compile(source: &str) -> anyhow::Result<Vec<Instruction>> {
+    compile_stmts(&parse(source)?)
+}
+
+// The compiled program as assembly which `asm::parse_asm` accepts.
+pub fn compile_to_asm(source: &str) -> anyhow::Result<String> {
+    let program = compile(source)?;
+    Ok(asm::disassemble(&program, &DisassembleOptions{ labels: true, addresses: false }))
+}
+
+// Compiles and runs `source`, checking it against a reference interpreter,
+// and returns the final acc and output.
+#[cfg(test)]
+fn check_compiled(source: &str) -> (i128, Vec<i32>) {
+    use std::collections::HashMap;
+    use super::vm::{VM, RunOutcome, Arithmetic, Width, Overflow};
+
+    // A reference interpreter with wrapping i32 arithmetic.
+    fn eval(e: &Expr, vars: &HashMap<String, i64>) -> i64 {
+        match e {
+            Expr::Num(n) => *n as i64,
+            Expr::Var(name) => vars.get(name).copied().unwrap_or(0),
+            Expr::Neg(e) => (eval(e, vars) as i32).wrapping_neg() as i64,
+            Expr::Bin(op, l, r) => {
+                let (l, r) = (eval(l, vars) as i32, eval(r, vars) as i32);
+                (match op {
+                    BinOp::Add => l.wrapping_add(r),
+                    BinOp::Sub => l.wrapping_sub(r),
+                    BinOp::Mul => l.wrapping_mul(r),
+                }) as i64
+            },
+        }
+    }
+
+    fn run(stmts: &[Stmt], vars: &mut HashMap<String, i64>, out: &mut Vec<i32>) {
+        for s in stmts {
+            match s {
+                Stmt::Assign(name, e) => {
+                    let v = eval(e, vars);
+                    vars.insert(name.clone(), v);
+                },
+                Stmt::While(c, body) => while test(c, vars) { run(body, vars, out) },
+                Stmt::If(c, then, otherwise) => run(if test(c, vars) { then } else { otherwise }, vars, out),
+                Stmt::Out(e) => out.push(eval(e, vars) as i32),
+            }
+        }
+    }
+
+    fn test(c: &Cond, vars: &HashMap<String, i64>) -> bool {
+        match c {
+            Cond::NonZero(e) => eval(e, vars) != 0,
+            Cond::Cmp(op, l, r) => {
+                let (l, r) = (eval(l, vars), eval(r, vars));
+                match op {
+                    Cmp::Lt => l < r,
+                    Cmp::Le => l <= r,
+                    Cmp::Gt => l > r,
+                    Cmp::Ge => l >= r,
+                    Cmp::Eq => l == r,
+                    Cmp::Ne => l != r,
+                }
+            },
+        }
+    }
+
+    let stmts = parse(source).unwrap();
+    let mut vars = HashMap::new();
+    let mut expected = Vec::new();
+    run(&stmts, &mut vars, &mut expected);
+
+    let mut vm = VM::new(compile(source).unwrap(), Default::default());
+    vm.set_arithmetic(Arithmetic{ width: Width::I32, overflow: Overflow::Wrapping }).unwrap();
+    assert!(matches!(vm.run_with_fuel(100_000).unwrap(), RunOutcome::Terminated(_)), "{}", source);
+    assert_eq!(vm.output(0), &expected[..], "{}", source);
+    assert_eq!(vm.state().acc, vars.get("acc").copied().unwrap_or(0) as i128, "{}", source);
+
+    // The assembly text gives the same program.
+    assert_eq!(asm::parse_asm(&compile_to_asm(source).unwrap()).unwrap(), vm.instructions());
+    (vm.state().acc, expected)
+}
+
+#[test]
+fn test_lang() {
+    use super::vm::{VM, RunOutcome, Arithmetic, Width, Overflow};
+
+    assert_eq!(check_compiled("
+        # factorial
+        n = 6;
+        acc = 1;
+        while n > 1 {
+            acc = acc * n;
+            n = n - 1;
+        }
+    "), (720, vec![]));
+
+    assert_eq!(check_compiled("
+        a = 0; b = 1; i = 0;
+        while i < 10 {
+            out a;
+            t = a + b; a = b; b = t;
+            i = i + 1;
+        }
+    ").1, vec![0, 1, 1, 2, 3, 5, 8, 13, 21, 34]);
+
+    // gcd by subtraction, with every comparison.
+    assert_eq!(check_compiled("
+        x = 1071; y = 462;
+        while x != y {
+            if x > y { x = x - y; } else { y = y - x; }
+        }
+        acc = x;
+        if x == 21 { out 1; }
+        if x <= 20 { out 2; } else if x >= 22 { out 3; } else { out 4; }
+        if x - 21 { out 5; }
+        if x < 22 { acc = acc + 100; }
+    "), (121, vec![1, 4]));
+
+    // Deeply nested expressions use temporaries.
+    check_compiled("x = 3; y = -4; out (x - (y * (x + (y - (x * 2))))) * -(y - x + 7) - x * y;");
+
+    // i32::MIN has no negation, so subtracting it multiplies by -1 instead,
+    // which wraps back to i32::MIN.
+    let min = || Box::new(Expr::Num(i32::MIN));
+    let acc = || Box::new(Expr::Var("acc".to_string()));
+    let x = || Box::new(Expr::Var("x".to_string()));
+    let program = compile_stmts(&[
+        Stmt::Assign("x".to_string(), Expr::Num(5)),
+        Stmt::Assign("acc".to_string(), Expr::Bin(BinOp::Sub, acc(), min())),
+        Stmt::Out(Expr::Bin(BinOp::Sub, x(), min())),
+    ]).unwrap();
+    let mut vm = VM::new(program, Default::default());
+    vm.set_arithmetic(Arithmetic{ width: Width::I32, overflow: Overflow::Wrapping }).unwrap();
+    assert!(matches!(vm.run().unwrap(), RunOutcome::Terminated(_)));
+    assert_eq!((vm.state().acc, vm.output(0)), (i32::MIN as i128, &[5i32.wrapping_sub(i32::MIN)][..]));
+
+    let errors = parse("x = 1;\nwhile x < {\n}").unwrap_err();
+    assert_eq!(errors.0[0].to_string().lines().next(), Some("error: expected an expression"));
+    assert!(parse("if = 1;").is_err());
+    assert!(parse("x = 1").is_err());
+}
+
+// Random straight-line programs over a few variables.
+#[test]
+fn test_lang_differential() {
+    use super::testutil::Rng;
+
+    let mut rng = Rng::new(12345);
+    let mut next = |n: u64| rng.below(n);
+    fn gen(next: &mut dyn FnMut(u64) -> u64, depth: u32) -> String {
+        match if depth == 0 { next(2) } else { next(6) } {
+            0 => format!("{}", next(100)),
+            1 => ["x", "y", "z", "acc"][next(4) as usize].to_string(),
+            2 => format!("-{}", gen(next, depth - 1)),
+            _ => format!("({} {} {})", gen(next, depth - 1), ["+", "-", "*"][next(3) as usize], gen(next, depth - 1)),
+        }
+    }
+    for _ in 0..200 {
+        let mut source = String::new();
+        for _ in 0..5 {
+            let var = ["x", "y", "z", "acc"][next(4) as usize];
+            source += &format!("{} = {};\nout {};\n", var, gen(&mut next, 4), var);
+        }
+        let (acc, out) = check_compiled(&source);
+        assert!(acc.abs() <= i32::MAX as i128 + 1 && out.len() == 5);
+    }
+}
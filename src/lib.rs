@@ -2,4 +2,19 @@ pub mod toboggan;
 pub mod passport;
 pub mod bags;
 pub mod vm;
-pub mod asm;
\ No newline at end of file
+pub mod asm;
+pub mod cfg;
+pub mod profile;
+pub mod codec;
+pub mod snapshot;
+pub mod optimise;
+pub mod analysis;
+pub mod scheduler;
+pub mod bytecode;
+pub mod lint;
+pub mod lang;
+pub mod docking;
+pub mod rules;
+pub mod kv;
+#[cfg(test)]
+mod testutil;
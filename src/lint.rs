@@ -0,0 +1,134 @@
+use std::fmt;
+use super::vm::Instruction;
+use super::asm::{Program, SourceLocation};
+use super::cfg::Cfg;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LintKind {
+    // A branch to an absolute ip outside the program and not its end.
+    JumpOutOfRange(i32),
+    SelfLoop,
+    // The first of this many unreachable instructions.
+    Unreachable(usize),
+    DisabledJump,
+    AccZero,
+}
+
+impl LintKind {
+    pub const NAMES: &'static [&'static str] = &[
+        "jump-out-of-range",
+        "self-loop",
+        "unreachable",
+        "disabled-jump",
+        "acc-zero",
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LintKind::JumpOutOfRange(_) => "jump-out-of-range",
+            LintKind::SelfLoop => "self-loop",
+            LintKind::Unreachable(_) => "unreachable",
+            LintKind::DisabledJump => "disabled-jump",
+            LintKind::AccZero => "acc-zero",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lint {
+    pub ip: usize,
+    pub kind: LintKind,
+    pub location: Option<SourceLocation>,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            LintKind::JumpOutOfRange(target) => write!(f, "jump to {} is outside the program", target),
+            LintKind::SelfLoop => write!(f, "`jmp +0` loops forever"),
+            LintKind::Unreachable(1) => write!(f, "unreachable instruction"),
+            LintKind::Unreachable(n) => write!(f, "{} unreachable instructions", n),
+            LintKind::DisabledJump => write!(f, "`nop` with an argument looks like a disabled `jmp`"),
+            LintKind::AccZero => write!(f, "`acc +0` has no effect"),
+        }
+    }
+}
+
+pub fn lint(instructions: &[Instruction]) -> Vec<Lint> {
+    let len = instructions.len() as i32;
+    let mut lints = Vec::new();
+    let mut push = |ip: usize, kind| lints.push(Lint{ ip, kind, location: None });
+
+    let cfg = Cfg::new(instructions);
+    let reachable = cfg.reachable();
+    let mut unreachable_from = None;
+    for (ip, inst) in instructions.iter().enumerate() {
+        let is_reachable = reachable[cfg.block_of(ip).unwrap()];
+        match (is_reachable, unreachable_from) {
+            (false, None) => unreachable_from = Some(ip),
+            (true, Some(start)) => {
+                push(start, LintKind::Unreachable(ip - start));
+                unreachable_from = None;
+            },
+            _ => {},
+        }
+
+        match *inst {
+            Instruction::Jmp(0) => push(ip, LintKind::SelfLoop),
+            Instruction::Nop(x) if x != 0 => push(ip, LintKind::DisabledJump),
+            Instruction::Acc(0) => push(ip, LintKind::AccZero),
+            _ => {},
+        }
+
+        if let Some(target) = inst.branch_target(ip as i32) {
+            if target < 0 || target > len {
+                push(ip, LintKind::JumpOutOfRange(target));
+            }
+        }
+    }
+
+    if let Some(start) = unreachable_from {
+        push(start, LintKind::Unreachable(instructions.len() - start));
+    }
+
+    lints.sort_by_key(|l| l.ip);
+    lints
+}
+
+// Lints an assembled program, locating each lint in its source.
+pub fn lint_program(program: &Program) -> Vec<Lint> {
+    let mut lints = lint(&program.instructions);
+    for l in lints.iter_mut() {
+        l.location = program.source_map.get(l.ip).copied();
+    }
+    lints
+}
+
+#[test]
+fn test_lint() {
+    use super::asm::assemble;
+
+    let program = assemble("\
+acc +1
+jmp skip
+acc +0
+nop +3
+skip: jmp +0
+jmp -10
+acc +0").unwrap();
+    let lints = lint_program(&program)
+        .into_iter()
+        .map(|l| (l.location.unwrap().line, l.kind))
+        .collect::<Vec<_>>();
+    assert_eq!(lints, vec![
+        (3, LintKind::AccZero),
+        (3, LintKind::Unreachable(2)),
+        (4, LintKind::DisabledJump),
+        (5, LintKind::SelfLoop),
+        (6, LintKind::JumpOutOfRange(-5)),
+        (6, LintKind::Unreachable(2)),
+        (7, LintKind::AccZero),
+    ]);
+    assert_eq!(lint(&program.instructions)[0].to_string(), "`acc +0` has no effect");
+    assert!(lint(&assemble("jlt a, 1, +2\nacc +1").unwrap().instructions).is_empty());
+}
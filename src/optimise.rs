@@ -0,0 +1,236 @@
+use super::vm::{Instruction, Arithmetic, Overflow};
+use super::cfg::Cfg;
+
+// Follows a branch target through jumps and instructions that do nothing,
+// stopping at the first instruction with an effect. Chains which loop forever
+// resolve to some point on the loop, so they still never terminate.
+fn resolve(instructions: &[Instruction], mut target: i32) -> i32 {
+    let mut visited = vec![false; instructions.len()];
+    while target >= 0 && (target as usize) < instructions.len() && !visited[target as usize] {
+        visited[target as usize] = true;
+        target = match instructions[target as usize] {
+            Instruction::Jmp(offset) => target.saturating_add(offset),
+            Instruction::Nop(_) | Instruction::Acc(0) => target + 1,
+            _ => break,
+        };
+    }
+    target
+}
+
+fn pass(instructions: &[Instruction], fold_acc: bool) -> Vec<Instruction> {
+    let len = instructions.len() as i32;
+
+    let threaded = instructions.iter()
+        .enumerate()
+        .map(|(ip, inst)| match inst.branch_target(ip as i32) {
+            Some(target) => inst.with_branch_offset(resolve(instructions, target) - ip as i32),
+            None => *inst,
+        })
+        .collect::<Vec<_>>();
+
+    // Emit the reachable blocks in their original order, dropping
+    // instructions that do nothing and, if allowed, folding runs of acc.
+    // Within a block only the first instruction can be a branch target, so a
+    // run never spans a target. Every old ip maps to the next instruction
+    // emitted at or after it.
+    let cfg = Cfg::new(&threaded);
+    let reachable = cfg.reachable();
+    let mut output: Vec<Instruction> = Vec::new();
+    let mut old_ips = Vec::new();
+    let mut new_ip = vec![None; threaded.len()];
+    let mut pending = Vec::new();
+    for (block, _) in cfg.blocks().iter().zip(reachable.iter()).filter(|(_, r)| **r) {
+        let mut run_start = false;
+        for (ip, &inst) in (block.start..).zip(&threaded[block.start..block.end]) {
+            pending.push(ip);
+            match inst {
+                Instruction::Nop(_) | Instruction::Acc(0) => continue,
+                Instruction::Jmp(1) => continue,
+                Instruction::Acc(b) if fold_acc && run_start => {
+                    if let Some(Instruction::Acc(a)) = output.last().copied() {
+                        if let Some(sum) = a.checked_add(b) {
+                            *output.last_mut().unwrap() = Instruction::Acc(sum);
+                            continue
+                        }
+                    }
+                },
+                _ => {},
+            }
+
+            for ip in pending.drain(..) {
+                new_ip[ip] = Some(output.len() as i32);
+            }
+            run_start = matches!(inst, Instruction::Acc(_));
+            output.push(inst);
+            old_ips.push(ip);
+        }
+    }
+
+    let new_len = output.len() as i32;
+    for ip in pending.drain(..) {
+        new_ip[ip] = Some(new_len);
+    }
+
+    // Targets outside the program stay outside it, at the same distance.
+    let map = |target: i32| {
+        if target < 0 {
+            target
+        } else if target >= len {
+            new_len + (target - len)
+        } else {
+            new_ip[target as usize].expect("branch target is reachable")
+        }
+    };
+
+    output.iter()
+        .zip(old_ips.iter())
+        .enumerate()
+        .map(|(ip, (inst, old_ip))| match inst.branch_target(*old_ip as i32) {
+            Some(target) => inst.with_branch_offset(map(target) - ip as i32),
+            None => *inst,
+        })
+        .collect()
+}
+
+// Shrinks a program without changing how it runs under `arithmetic`: its
+// final acc and registers, whether it terminates, and where it overflows.
+// Runs of acc are only folded with wrapping arithmetic, as checked or
+// saturating arithmetic can overflow partway through a run.
+pub fn optimise(instructions: &[Instruction], arithmetic: Arithmetic) -> Vec<Instruction> {
+    let fold_acc = arithmetic.overflow == Overflow::Wrapping;
+    let mut program = instructions.to_vec();
+    loop {
+        let next = pass(&program, fold_acc);
+        if next.len() >= program.len() {
+            return next;
+        }
+        program = next;
+    }
+}
+
+#[test]
+fn test_optimise() {
+    use super::vm::Width;
+    use Instruction::*;
+
+    let wrapping = Arithmetic{ width: Width::I32, overflow: Overflow::Wrapping };
+    let optimise = |program: &[Instruction]| optimise(program, wrapping);
+
+    let program = vec![
+        Acc(1), Acc(2), Jmp(2), Acc(100),
+        Nop(5), Acc(3), Jmp(2), Acc(-3),
+        Acc(4), Jmp(2), Jmp(-2), Acc(5),
+    ];
+    assert_eq!(optimise(&program), vec![Acc(15)]);
+
+    // Branch targets keep their meaning, including ones out of range.
+    let program = vec![Acc(1), Nop(0), Acc(2), Jmp(-2), Jmp(-10), Jmp(10)];
+    assert_eq!(optimise(&program), vec![Acc(1), Acc(2), Jmp(-1)]);
+    assert_eq!(optimise(&[Nop(0), Jmp(-1)]), vec![Jmp(0)]);
+    assert_eq!(optimise(&[Acc(1), Jmp(-7), Acc(2), Jmp(3)]), vec![Acc(1), Jmp(-7)]);
+    assert_eq!(optimise(&[Nop(0), Jmp(5), Acc(1)]), vec![Jmp(4)]);
+    assert_eq!(optimise(&[Acc(i32::MAX), Acc(1)]), vec![Acc(i32::MAX), Acc(1)]);
+
+    // Checked arithmetic must still overflow at the same acc, so runs stay.
+    assert_eq!(self::optimise(&[Acc(1), Nop(0), Acc(2)], Arithmetic::default()), vec![Acc(1), Acc(2)]);
+}
+
+// Runs `program` before and after optimising and checks that both end the
+// same way, with the same acc and registers.
+#[cfg(test)]
+fn check_optimised(program: &[Instruction], arithmetic: Arithmetic) {
+    use super::vm::{VM, State, OverflowError};
+
+    // How a run ended, with the acc and registers at that point.
+    #[derive(Debug, PartialEq)]
+    enum End {
+        Terminated(i128, [i32; 4]),
+        OutOfRange(i128, [i32; 4]),
+        Overflowed(i128, [i32; 4]),
+        OutOfFuel,
+    }
+
+    // Steps rather than using `VM::run`, whose loop detection depends on
+    // whether the program has conditional branches, which optimising can
+    // remove. A loop which would overflow must still overflow.
+    fn run(program: &[Instruction], arithmetic: Arithmetic, fuel: usize) -> End {
+        let mut vm = VM::new(program.to_vec(), Default::default());
+        vm.set_arithmetic(arithmetic).unwrap();
+        for _ in 0..fuel {
+            let State{ ip, acc, regs } = vm.state().clone();
+            if vm.is_terminated() {
+                return End::Terminated(acc, regs);
+            } else if ip < 0 || ip as usize > program.len() {
+                return End::OutOfRange(acc, regs);
+            }
+            if let Err(e) = vm.step() {
+                assert!(e.downcast_ref::<OverflowError>().is_some(), "{}", e);
+                return End::Overflowed(acc, regs);
+            }
+        }
+        End::OutOfFuel
+    }
+
+    let optimised = optimise(program, arithmetic);
+    assert!(optimised.len() <= program.len());
+    let (mut before, mut after) = (run(program, arithmetic, 10_000), run(&optimised, arithmetic, 10_000));
+    // The optimised program takes fewer steps, so it may finish within
+    // fuel that the original runs out of.
+    if before == End::OutOfFuel && after != End::OutOfFuel {
+        before = run(program, arithmetic, 1_000_000);
+    } else if after == End::OutOfFuel && before != End::OutOfFuel {
+        after = run(&optimised, arithmetic, 1_000_000);
+    }
+    assert_eq!(before, after, "{:?}\n{:?}\n{:?}", program, optimised, arithmetic);
+}
+
+#[test]
+fn test_optimise_day8() {
+    use super::vm::Width;
+
+    let checked = Arithmetic::default();
+    let wrapping = Arithmetic{ width: Width::I64, overflow: Overflow::Wrapping };
+
+    let day8 = super::asm::parse_asm(include_str!("bin/day8part1.txt")).unwrap();
+    check_optimised(&day8, checked);
+    let mut fixed = day8.clone();
+    fixed[253] = fixed[253].flipped().unwrap();
+    check_optimised(&fixed, checked);
+    check_optimised(&fixed, wrapping);
+    assert!(optimise(&fixed, wrapping).len() < fixed.len() / 2);
+}
+
+#[test]
+fn test_optimise_differential() {
+    use super::vm::{Register, Operand, Width};
+    use super::testutil::Rng;
+    use Instruction::*;
+
+    let checked = Arithmetic::default();
+    let wrapping = Arithmetic{ width: Width::I64, overflow: Overflow::Wrapping };
+
+    let mut rng = Rng::new(0x2545_f491);
+    let mut next = |n: u64| rng.below(n) as i32;
+    for _ in 0..1000 {
+        let len = 1 + next(12);
+        let conditional = next(2) == 0;
+        let program = (0..len)
+            .map(|_| {
+                let offset = next(len as u64 + 4) - 2 - next(len as u64);
+                match next(if conditional { 8 } else { 6 }) {
+                    // Large values make runs of acc overflow partway.
+                    0 => Acc(next(7) - 3),
+                    1 => Acc((next(7) - 3) << 29),
+                    2 => Nop(offset),
+                    3 => Jmp(offset),
+                    4 => Add(Register::A, Operand::Imm(next(3))),
+                    5 => Mul(Register::Acc, Operand::Imm(next(3))),
+                    6 => Jnz(Operand::Reg(Register::Acc), offset),
+                    _ => Jlt(Operand::Reg(Register::A), Operand::Imm(next(9)), offset),
+                }
+            })
+            .collect::<Vec<_>>();
+        check_optimised(&program, checked);
+        check_optimised(&program, wrapping);
+    }
+}
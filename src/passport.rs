@@ -1,331 +1,336 @@
-use nom::{
-    IResult,
-    bytes::complete::{take_until, take_till, tag},
-};
-use serde::{de, Serialize, Deserialize};
-use serde::de::{Error, Visitor};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
 use std::str::FromStr;
+use std::sync::OnceLock;
+use anyhow::anyhow;
+use super::rules::{Rules, Fields, ValidationReport};
+use super::kv;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Passport {
-    byr: i32,
-    iyr: i32,
-    eyr: i32,
-    hgt: String,
-    hcl: String,
-    ecl: String,
-    pid: String,
-    cid: Option<String>,
-}
-
-impl Passport {
-    pub fn parse(input: &str) -> IResult<&str, Passport, de::value::Error> {
-        let mut deserializer = PassportDeserializer{
-            input,
-            value: None,
-        };
-        let map_access = de::value::MapAccessDeserializer::new(&mut deserializer);
-        let t = Passport::deserialize(map_access).map_err(nom::Err::Error)?;
-        Ok((deserializer.input, t))
-    }
+pub use super::kv::split_blocks;
 
-    fn validate_height(h: &str) -> anyhow::Result<()> {
-        let idx = h.find(|c: char| !c.is_digit(10))
-            .ok_or_else(|| anyhow::Error::msg("height must have unit"))?;
-        let (num, unit) = h.split_at(idx);
-        let num = i32::from_str(num)?;
-
-        match unit {
-            "cm" => {
-                if num < 150 || num > 193 {
-                    Err(anyhow::Error::msg("invalid height num"))?;
-                }
-            },
-            "in" => {
-                if num < 59 || num > 76 {
-                    Err(anyhow::Error::msg("invalid height num"))?;
-                }
-            },
-            x => Err(anyhow::Error::msg(format!("unexpected unit {}", x)))?,
-        };
-
-        Ok(())
-    }
-
-    fn validate_hair_colour(h: &str) -> anyhow::Result<()> {
-        if h.len() != 7 {
-            Err(anyhow::Error::msg("hcl wrong length"))?;
-        }
+// The rules `Passport::validate` checks, as loaded by `Rules::from_json`.
+pub const DEFAULT_RULES: &str = include_str!("passport_rules.json");
 
-        let mut chars = h.chars();
-        if chars.next() != Some('#') {
-            Err(anyhow::Error::msg("hcl must start with a"))
-        } else {
-            for char in chars {
-                if ('0'..='9').contains(&char) {
-                    continue
-                }
-
-                if ('a'..='f').contains(&char) {
-                    continue
-                }
-
-                Err(anyhow::Error::msg("invalid hcl"))?;
-            }
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Height {
+    Cm(u32),
+    In(u32),
+}
 
-            Ok(())
+impl Height {
+    pub fn cm(&self) -> f64 {
+        match *self {
+            Height::Cm(n) => n as f64,
+            Height::In(n) => n as f64 * 2.54,
         }
     }
 
-    fn validate_eye_colour(e: &str) -> anyhow::Result<()> {
-        match e {
-            "amb" | "blu" | "brn" | "gry" | "grn" | "hzl" | "oth" => Ok(()),
-            c => Err(anyhow::Error::msg(format!("invalid ecl: {}", c)))
+    pub fn inches(&self) -> f64 {
+        match *self {
+            Height::Cm(n) => n as f64 / 2.54,
+            Height::In(n) => n as f64,
         }
     }
+}
 
-    fn validate_passport_id(i: &str) -> anyhow::Result<()> {
-        if i.len() != 9 {
-            Err(anyhow::Error::msg("pid must be 9 characters"))?;
-        }
+impl FromStr for Height {
+    type Err = anyhow::Error;
 
-        if !i.chars().all(|c| c.is_digit(10)) {
-            Err(anyhow::Error::msg("pid must be numbers"))?;
+    fn from_str(s: &str) -> anyhow::Result<Height> {
+        let idx = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (num, unit) = s.split_at(idx);
+        let num = u32::from_str(num).map_err(|_| anyhow!("height {:?} must start with a number", s))?;
+        match unit {
+            "cm" => Ok(Height::Cm(num)),
+            "in" => Ok(Height::In(num)),
+            "" => Err(anyhow!("height {:?} needs a unit of cm or in", s)),
+            _ => Err(anyhow!("height {:?} has unit {:?}, expected cm or in", s, unit)),
         }
-
-        Ok(())
     }
+}
 
-    pub fn validate(&self) -> anyhow::Result<()> {
-        if self.byr < 1920 || self.byr > 2002 {
-            Err(anyhow::Error::msg("byr must be between 1920 and 2002"))
-        } else if self.iyr < 2010 || self.iyr > 2020 {
-            Err(anyhow::Error::msg("iyr must be between 2010 and 2020"))
-        } else if self.eyr < 2020 || self.eyr > 2030 {
-            Err(anyhow::Error::msg("eyr is invalid"))
-        } else {
-            Passport::validate_height(&self.hgt)?;
-            Passport::validate_hair_colour(&self.hcl)?;
-            Passport::validate_eye_colour(&self.ecl)?;
-            Passport::validate_passport_id(&self.pid)?;
-            Ok(())
+impl fmt::Display for Height {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Height::Cm(n) => write!(f, "{}cm", n),
+            Height::In(n) => write!(f, "{}in", n),
         }
     }
 }
 
-struct FieldDeserializer<'de> {
-    input: &'de str,
+// A colour written as `#rrggbb` in lowercase hex.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct HairColour {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
 }
 
-impl<'de> de::Deserializer<'de> for FieldDeserializer<'de> {
-    type Error = de::value::Error;
+impl FromStr for HairColour {
+    type Err = anyhow::Error;
 
-    fn deserialize_any<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_str(self.input)
-    }
-
-    fn deserialize_bool<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_bool(FromStr::from_str(self.input).map_err(|e| de::value::Error::custom(&e))?)
-    }
-
-    fn deserialize_i8<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_i8(FromStr::from_str(self.input).map_err(|e| de::value::Error::custom(&e))?)
-    }
-
-    fn deserialize_i16<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_i16(FromStr::from_str(self.input).map_err(|e| de::value::Error::custom(&e))?)
+    fn from_str(s: &str) -> anyhow::Result<HairColour> {
+        let hex = s.strip_prefix('#')
+            .filter(|h| h.len() == 6 && h.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c)))
+            .ok_or_else(|| anyhow!("hair colour {:?} must be # and six lowercase hex digits", s))?;
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        Ok(HairColour{ r: channel(0), g: channel(2), b: channel(4) })
     }
+}
 
-    fn deserialize_i32<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_i32(FromStr::from_str(self.input).map_err(|e| de::value::Error::custom(&e))?)
+impl fmt::Display for HairColour {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
     }
+}
 
-    fn deserialize_i64<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_i64(FromStr::from_str(self.input).map_err(|e| de::value::Error::custom(&e))?)
-    }
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum EyeColour {
+    Amber,
+    Blue,
+    Brown,
+    Grey,
+    Green,
+    Hazel,
+    Other,
+}
 
-    fn deserialize_u8<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_u8(FromStr::from_str(self.input).map_err(|e| de::value::Error::custom(&e))?)
+impl EyeColour {
+    pub const ALL: &'static [EyeColour] = &[
+        EyeColour::Amber,
+        EyeColour::Blue,
+        EyeColour::Brown,
+        EyeColour::Grey,
+        EyeColour::Green,
+        EyeColour::Hazel,
+        EyeColour::Other,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            EyeColour::Amber => "amb",
+            EyeColour::Blue => "blu",
+            EyeColour::Brown => "brn",
+            EyeColour::Grey => "gry",
+            EyeColour::Green => "grn",
+            EyeColour::Hazel => "hzl",
+            EyeColour::Other => "oth",
+        }
     }
+}
 
-    fn deserialize_u16<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_u16(FromStr::from_str(self.input).map_err(|e| de::value::Error::custom(&e))?)
-    }
+impl FromStr for EyeColour {
+    type Err = anyhow::Error;
 
-    fn deserialize_u32<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_u32(FromStr::from_str(self.input).map_err(|e| de::value::Error::custom(&e))?)
+    fn from_str(s: &str) -> anyhow::Result<EyeColour> {
+        EyeColour::ALL.iter()
+            .copied()
+            .find(|e| e.code() == s)
+            .ok_or_else(|| anyhow!("eye colour {:?} must be one of amb, blu, brn, gry, grn, hzl or oth", s))
     }
+}
 
-    fn deserialize_u64<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_u64(FromStr::from_str(self.input).map_err(|e| de::value::Error::custom(&e))?)
+impl fmt::Display for EyeColour {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
     }
+}
 
-    fn deserialize_f32<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_f32(FromStr::from_str(self.input).map_err(|e| de::value::Error::custom(&e))?)
-    }
+// A nine digit number, which may have leading zeros.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PassportId(pub u32);
 
-    fn deserialize_f64<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_f64(FromStr::from_str(self.input).map_err(|e| de::value::Error::custom(&e))?)
-    }
+impl FromStr for PassportId {
+    type Err = anyhow::Error;
 
-    fn deserialize_char<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        if self.input.len() != 1 {
-            Err(de::value::Error::custom("char must be length 1"))
-        } else {
-            visitor.visit_char(self.input.as_bytes()[0] as char)
+    fn from_str(s: &str) -> anyhow::Result<PassportId> {
+        if s.len() != 9 || !s.chars().all(|c| c.is_ascii_digit()) {
+            Err(anyhow!("passport id {:?} must be nine digits", s))?;
         }
+        Ok(PassportId(s.parse()?))
     }
+}
 
-    fn deserialize_str<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_str(self.input)
-    }
-
-    fn deserialize_string<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_string(String::from(self.input))
-    }
-
-    fn deserialize_bytes<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_bytes(self.input.as_bytes())
-    }
-
-    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_byte_buf(self.input.as_bytes().to_vec())
-    }
-
-    fn deserialize_option<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_some(self)
+impl fmt::Display for PassportId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:09}", self.0)
     }
+}
 
-    fn deserialize_unit<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_unit()
-    }
+// The `TryFrom<String>` and `Into<String>` impls serde's `try_from` and `into`
+// attributes need, in terms of `FromStr` and `Display`.
+macro_rules! string_conversions {
+    ($($t:ty),*) => {$(
+        impl TryFrom<String> for $t {
+            type Error = anyhow::Error;
 
-    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_unit()
-    }
+            fn try_from(s: String) -> anyhow::Result<$t> {
+                s.parse()
+            }
+        }
 
-    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_newtype_struct(self)
-    }
+        impl From<$t> for String {
+            fn from(v: $t) -> String {
+                v.to_string()
+            }
+        }
+    )*};
+}
 
-    fn deserialize_seq<V>(self, _visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        Err(de::value::Error::custom("seq not supported"))
-    }
+string_conversions!(Height, HairColour, EyeColour, PassportId);
 
-    fn deserialize_tuple<V>(self, _len: usize, _visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        Err(de::value::Error::custom("tuple not supported"))
-    }
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Passport {
+    pub byr: i32,
+    pub iyr: i32,
+    pub eyr: i32,
+    pub hgt: Height,
+    pub hcl: HairColour,
+    pub ecl: EyeColour,
+    pub pid: PassportId,
+    pub cid: Option<String>,
+}
 
-    fn deserialize_tuple_struct<V>(self, _name: &'static str, _len: usize, _visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        Err(de::value::Error::custom("tuple not supported"))
-    }
+// `DEFAULT_RULES`, parsed on first use.
+pub fn default_rules() -> &'static Rules {
+    static RULES: OnceLock<Rules> = OnceLock::new();
+    RULES.get_or_init(|| Rules::from_json(DEFAULT_RULES).expect("default rules are valid"))
+}
 
-    fn deserialize_map<V>(self, _visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        Err(de::value::Error::custom("map not supported"))
-    }
+// The fields every passport needs, `cid` being optional.
+pub const REQUIRED_FIELDS: &[&str] = &["byr", "iyr", "eyr", "hgt", "hcl", "ecl", "pid"];
 
-    fn deserialize_struct<V>(self, _name: &'static str, _fields: &'static [&'static str], _visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        Err(de::value::Error::custom("struct not supported"))
-    }
+// The raw fields of a passport, without checking any of them.
+pub fn parse_fields(input: &str) -> Result<BTreeMap<String, String>, kv::Error> {
+    kv::from_str(input)
+}
 
-    fn deserialize_enum<V>(self, _name: &'static str, _variants: &'static [&'static str], _visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        Err(de::value::Error::custom("enum not supported"))
+impl Passport {
+    pub fn parse(input: &str) -> Result<Passport, kv::Error> {
+        kv::from_str(input)
     }
 
-    fn deserialize_identifier<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_str(self.input)
+    // Validates against the default rules, see `DEFAULT_RULES`.
+    pub fn validate(&self) -> ValidationReport {
+        self.validate_with(default_rules())
     }
 
-    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<<V as Visitor<'de>>::Value, Self::Error> where
-        V: Visitor<'de> {
-        visitor.visit_str(self.input)
+    pub fn validate_with(&self, rules: &Rules) -> ValidationReport {
+        rules.validate(self)
     }
 }
 
-struct PassportDeserializer<'de> {
-    input: &'de str,
-    value: Option<&'de str>,
-}
-
-impl<'de> de::MapAccess<'de> for PassportDeserializer<'de> {
-    type Error = de::value::Error;
-
-    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<<K as de::DeserializeSeed<'de>>::Value>, Self::Error> where
-        K: de::DeserializeSeed<'de> {
-        if self.input.is_empty() {
-            return Ok(None);
-        }
-
-        let (input, (key, value)) = parse_key_pair(self.input).map_err(Error::custom)?;
-        self.input = input;
-        self.value = Some(value);
-        seed.deserialize(FieldDeserializer{input: key}).map(Some)
-    }
-
-    fn next_value_seed<V>(&mut self, seed: V) -> Result<<V as de::DeserializeSeed<'de>>::Value, Self::Error> where
-        V: de::DeserializeSeed<'de> {
-        if let Some(value) = self.value.take() {
-            seed.deserialize(FieldDeserializer{input: value})
-        } else {
-            Err(de::value::Error::custom("called next_value without next_key"))
+impl Fields for Passport {
+    fn field(&self, name: &str) -> Option<String> {
+        match name {
+            "byr" => Some(self.byr.to_string()),
+            "iyr" => Some(self.iyr.to_string()),
+            "eyr" => Some(self.eyr.to_string()),
+            "hgt" => Some(self.hgt.to_string()),
+            "hcl" => Some(self.hcl.to_string()),
+            "ecl" => Some(self.ecl.to_string()),
+            "pid" => Some(self.pid.to_string()),
+            "cid" => self.cid.clone(),
+            _ => None,
         }
     }
 }
 
-fn skip_whitespace(input: &str) -> &str {
-    input.trim_start()
+pub fn parse_list(input: &str) -> Result<Vec<Passport>, kv::Error> {
+    kv::from_str(input)
 }
 
-fn parse_key_pair(input: &str) -> IResult<&str, (&str, &str)> {
-    let input = skip_whitespace(input);
-    let (input, key) = take_until(":")(input)?;
-    let (input, _) = tag(":")(input)?;
-    let (input, value) = take_till(|c: char| c.is_whitespace())(input)?;
-    let input = skip_whitespace(input);
-    Ok((input, (key, value)))
+#[test]
+fn test_validate() {
+    use super::rules::ErrorKind;
+
+    let input = "\
+eyr:1972 cid:100
+hcl:#18171d ecl:amb hgt:170 pid:186cm iyr:2018 byr:1926
+
+pid:087499704 hgt:74in ecl:grn iyr:2012 eyr:2030 byr:1980
+hcl:#623a2f
+
+hgt:59cm ecl:zzz
+eyr:2038 hcl:74454a iyr:2023
+pid:3556412378 byr:2007
+
+hcl:#888785
+hgt:164cm byr:2001 iyr:2015 cid:88
+pid:545766238 ecl:hzl
+eyr:2022";
+    let blocks = split_blocks(input).collect::<Vec<_>>();
+
+    // Badly formatted fields fail to parse, naming the field.
+    let error = |block| Passport::parse(block).unwrap_err().to_string();
+    assert_eq!(error(blocks[0]), "2:25: hgt: height \"170\" needs a unit of cm or in");
+    assert_eq!(error(blocks[2]), "1:14: ecl: eye colour \"zzz\" must be one of amb, blu, brn, gry, grn, hzl or oth");
+
+    // The rules still report every problem with the raw fields.
+    let rules = default_rules();
+    let fields = parse_fields(blocks[0]).unwrap();
+    assert_eq!(rules.validate(&fields).to_string(),
+        "eyr 1972 is out of range, expected 2020 to 2030; hgt 170 has a bad unit, expected cm or in; \
+pid \"186cm\" is badly formatted, expected [0-9]{9}");
+    let fields = parse_fields(blocks[2]).unwrap();
+    let kinds = rules.validate(&fields).errors.iter().map(|e| (e.field.clone(), e.kind)).collect::<Vec<_>>();
+    assert_eq!(kinds, vec![
+        ("byr".to_string(), ErrorKind::OutOfRange),
+        ("iyr".to_string(), ErrorKind::OutOfRange),
+        ("eyr".to_string(), ErrorKind::OutOfRange),
+        ("hgt".to_string(), ErrorKind::OutOfRange),
+        ("hcl".to_string(), ErrorKind::BadFormat),
+        ("ecl".to_string(), ErrorKind::NotInSet),
+        ("pid".to_string(), ErrorKind::BadFormat),
+    ]);
+
+    let p = Passport::parse(blocks[1]).unwrap();
+    assert!(p.validate().is_valid());
+    assert_eq!(p.hgt, Height::In(74));
+    assert!((p.hgt.cm() - 187.96).abs() < 1e-9);
+    assert_eq!(p.hcl, HairColour{ r: 0x62, g: 0x3a, b: 0x2f });
+    assert_eq!(p.ecl, EyeColour::Green);
+    assert_eq!((p.pid, p.pid.to_string()), (PassportId(87499704), "087499704".to_string()));
+    let json = serde_json::to_string(&p).unwrap();
+    assert!(json.contains(r##""hgt":"74in","hcl":"#623a2f","ecl":"grn","pid":"087499704""##));
+    assert_eq!(serde_json::from_str::<Passport>(&json).unwrap().pid, p.pid);
+
+    let p = Passport::parse("byr:2001 iyr:2015 eyr:2022 hgt:59cm hcl:#888785 ecl:hzl pid:545766238").unwrap();
+    assert_eq!(p.validate().errors[0].kind, ErrorKind::OutOfRange);
+    let lenient = Rules::from_json(r#"[{ "field": "hgt", "units": { "cm": { "min": 0, "max": 300 } } }]"#).unwrap();
+    assert!(p.validate_with(&lenient).is_valid());
+
+    assert!("#ABCDEF".parse::<HairColour>().is_err());
+    assert!("12345678".parse::<PassportId>().is_err());
+    assert!("60ft".parse::<Height>().is_err());
+    assert!(serde_json::from_str::<EyeColour>(r#""xyz""#).is_err());
 }
 
-pub fn split_blocks(input: &str) -> impl Iterator<Item=&str> {
-    input.split("\n\n")
-}
+#[test]
+fn test_serialize() {
+    let input = "\
+pid:087499704 hgt:74in ecl:grn iyr:2012 eyr:2030 byr:1980
+hcl:#623a2f
 
-pub fn parse_list(input: &str) -> Result<Vec<Passport>, nom::Err<de::value::Error>> {
-    split_blocks(input)
-        .map(Passport::parse)
-        .map(|r| r.and_then(|(i, p)| {
-            if i.is_empty() {
-                Ok(p)
-            } else {
-                Err(nom::Err::Error(de::value::Error::custom("extra input after passport")))
-            }
-        }))
-        .collect()
+hcl:#888785
+hgt:164cm byr:2001 iyr:2015 cid:88
+pid:545766238 ecl:hzl
+eyr:2022";
+    let passports = parse_list(input).unwrap();
+
+    let output = kv::to_string(&passports).unwrap();
+    assert_eq!(output, "\
+byr:1980 iyr:2012 eyr:2030 hgt:74in hcl:#623a2f ecl:grn pid:087499704
+
+byr:2001 iyr:2015 eyr:2022 hgt:164cm hcl:#888785 ecl:hzl pid:545766238 cid:88");
+    assert_eq!(parse_list(&output).unwrap(), passports);
+    for p in passports.iter() {
+        assert_eq!(Passport::parse(&kv::to_string(p).unwrap()).unwrap(), *p);
+    }
 }
@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use serde::Serialize;
+use super::vm::Instruction;
+use super::cfg::Cfg;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct InstructionCount {
+    pub ip: usize,
+    pub instruction: String,
+    pub count: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct OpcodeCount {
+    pub mnemonic: &'static str,
+    pub count: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BlockCount {
+    pub block: usize,
+    pub start: usize,
+    pub end: usize,
+    pub entries: u64,
+    pub steps: u64,
+}
+
+// A report built from per-ip execution counts, with every list sorted
+// hottest first.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Profile {
+    pub total_steps: u64,
+    pub instructions: Vec<InstructionCount>,
+    pub opcodes: Vec<OpcodeCount>,
+    pub blocks: Vec<BlockCount>,
+    #[serde(skip)]
+    counts: Vec<u64>,
+}
+
+impl Profile {
+    // The counts must be for this program, such as from `VM::profile`.
+    pub fn new(instructions: &[Instruction], counts: &[u64]) -> anyhow::Result<Profile> {
+        if counts.len() != instructions.len() {
+            Err(anyhow::anyhow!("profile has {} counts for {} instructions", counts.len(), instructions.len()))?;
+        }
+
+        let mut by_ip = instructions.iter()
+            .zip(counts.iter().copied())
+            .enumerate()
+            .filter(|(_, (_, count))| *count > 0)
+            .map(|(ip, (inst, count))| InstructionCount{
+                ip,
+                instruction: inst.to_string(),
+                count,
+            })
+            .collect::<Vec<_>>();
+        by_ip.sort_by(|a, b| b.count.cmp(&a.count).then(a.ip.cmp(&b.ip)));
+
+        let mut by_opcode = BTreeMap::new();
+        for (inst, count) in instructions.iter().zip(counts.iter().copied()) {
+            *by_opcode.entry(inst.mnemonic()).or_insert(0) += count;
+        }
+        let mut opcodes = by_opcode.into_iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(mnemonic, count)| OpcodeCount{ mnemonic, count })
+            .collect::<Vec<_>>();
+        opcodes.sort_by(|a, b| b.count.cmp(&a.count).then(a.mnemonic.cmp(b.mnemonic)));
+
+        let cfg = Cfg::new(instructions);
+        let mut blocks = cfg.blocks().iter()
+            .enumerate()
+            .map(|(idx, block)| BlockCount{
+                block: idx,
+                start: block.start,
+                end: block.end,
+                entries: counts[block.start],
+                steps: counts[block.start..block.end].iter().sum(),
+            })
+            .filter(|b| b.steps > 0)
+            .collect::<Vec<_>>();
+        blocks.sort_by(|a, b| b.steps.cmp(&a.steps).then(a.block.cmp(&b.block)));
+
+        Ok(Profile{
+            total_steps: counts.iter().sum(),
+            instructions: by_ip,
+            opcodes,
+            blocks,
+            counts: counts.to_vec(),
+        })
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        if self.total_steps == 0 {
+            0.0
+        } else {
+            100.0 * count as f64 / self.total_steps as f64
+        }
+    }
+
+    pub fn report(&self, top: usize) -> String {
+        let mut out = String::new();
+        writeln!(out, "total steps: {}", self.total_steps).unwrap();
+
+        writeln!(out, "\nhottest instructions:").unwrap();
+        for i in self.instructions.iter().take(top) {
+            writeln!(out, "  {:04} {:<16} {:>12} {:>6.2}%", i.ip, i.instruction, i.count, self.percent(i.count)).unwrap();
+        }
+
+        writeln!(out, "\nopcodes:").unwrap();
+        for o in self.opcodes.iter() {
+            writeln!(out, "  {:<4} {:>12} {:>6.2}%", o.mnemonic, o.count, self.percent(o.count)).unwrap();
+        }
+
+        writeln!(out, "\nhottest blocks:").unwrap();
+        for b in self.blocks.iter().take(top) {
+            writeln!(out, "  b{:<5} {:04}..{:04} entered {:>10} steps {:>12} {:>6.2}%",
+                b.block, b.start, b.end, b.entries, b.steps, self.percent(b.steps)).unwrap();
+        }
+
+        out
+    }
+
+    // The program's disassembly with each line prefixed by its execution
+    // count and share of all steps.
+    pub fn annotate(&self, instructions: &[Instruction]) -> String {
+        let mut out = String::new();
+        for (ip, inst) in instructions.iter().enumerate() {
+            let count = self.counts.get(ip).copied().unwrap_or(0);
+            if count == 0 {
+                writeln!(out, "{:>12} {:>7} | {:04} {}", "", "", ip, inst).unwrap();
+            } else {
+                writeln!(out, "{:>12} {:>6.2}% | {:04} {}", count, self.percent(count), ip, inst).unwrap();
+            }
+        }
+        out
+    }
+}
+
+#[test]
+fn test_profile() {
+    use super::vm::{VM, Register, Operand};
+    use Instruction::*;
+
+    let program = vec![
+        Mov(Register::A, Operand::Imm(3)),
+        Acc(1),
+        Add(Register::A, Operand::Imm(-1)),
+        Jnz(Operand::Reg(Register::A), -2),
+        Nop(0),
+    ];
+    let mut vm = VM::new(program.clone(), Default::default());
+    vm.set_profiling(true);
+    assert!(vm.run().unwrap().is_terminated());
+
+    let profile = Profile::new(&program, vm.profile().unwrap()).unwrap();
+    assert_eq!(profile.total_steps, 11);
+    assert_eq!(profile.instructions[0], InstructionCount{ ip: 1, instruction: "acc +1".to_string(), count: 3 });
+    assert_eq!(profile.opcodes[0], OpcodeCount{ mnemonic: "acc", count: 3 });
+    assert_eq!(profile.blocks[0], BlockCount{ block: 1, start: 1, end: 4, entries: 3, steps: 9 });
+
+    let json = serde_json::to_value(&profile).unwrap();
+    assert_eq!(json["blocks"][0]["steps"], 9);
+    assert!(profile.annotate(&program).contains("3  27.27% | 0001 acc +1"));
+
+    // Counts from a different build of the program are refused.
+    assert!(Profile::new(&program, &vm.profile().unwrap()[..3]).is_err());
+    assert!(Profile::new(&program[..3], vm.profile().unwrap()).is_err());
+}
@@ -0,0 +1,429 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use anyhow::anyhow;
+use serde::{Serialize, Deserialize};
+
+// A record whose fields can be checked against rules by name.
+pub trait Fields {
+    fn field(&self, name: &str) -> Option<String>;
+}
+
+impl Fields for BTreeMap<String, String> {
+    fn field(&self, name: &str) -> Option<String> {
+        self.get(name).cloned()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Range {
+    pub min: i64,
+    pub max: i64,
+}
+
+impl Range {
+    // Checks an integer against the range, with `unit` added to what was
+    // expected: "an integer cm" or "150 to 193cm".
+    fn check(&self, value: &str, unit: &str) -> Result<(), (ErrorKind, String)> {
+        let n = i64::from_str(value).map_err(|_| {
+            let expected = if unit.is_empty() { "an integer".to_string() } else { format!("an integer {}", unit) };
+            (ErrorKind::BadFormat, expected)
+        })?;
+        if n < self.min || n > self.max {
+            Err((ErrorKind::OutOfRange, format!("{} to {}{}", self.min, self.max, unit)))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Atom {
+    Any,
+    // Inclusive character ranges, matching any char in one unless negated.
+    Class(bool, usize, usize),
+}
+
+// An anchored pattern with a regex-like syntax: literal characters, `.`,
+// classes such as `[0-9a-f]` or `[^a]`, `\` escapes, and the quantifiers
+// `?`, `*`, `+`, `{n}` and `{n,m}`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Pattern {
+    source: String,
+    ranges: Vec<(char, char)>,
+    items: Vec<(Atom, usize, usize)>,
+}
+
+impl Pattern {
+    pub fn as_str(&self) -> &str { &self.source }
+
+    pub fn is_match(&self, s: &str) -> bool {
+        let chars = s.chars().collect::<Vec<_>>();
+        let mut failed = vec![false; (self.items.len() + 1) * (chars.len() + 1)];
+        self.match_from(0, 0, &chars, &mut failed)
+    }
+
+    fn matches_atom(&self, atom: Atom, c: char) -> bool {
+        match atom {
+            Atom::Any => true,
+            Atom::Class(negated, start, end) => self.ranges[start..end]
+                .iter()
+                .any(|&(lo, hi)| lo <= c && c <= hi) != negated,
+        }
+    }
+
+    // Tries the longest run of `item` at `at` first, backtracking to shorter
+    // runs if the rest of the pattern then fails. Rules files are editable,
+    // so `failed` remembers each (item, at) that can't match, which keeps
+    // patterns like `a*a*a*b` from taking exponential time.
+    fn match_from(&self, item: usize, at: usize, chars: &[char], failed: &mut [bool]) -> bool {
+        let (atom, min, max) = match self.items.get(item) {
+            Some(x) => *x,
+            None => return at == chars.len(),
+        };
+
+        let key = item * (chars.len() + 1) + at;
+        if failed[key] {
+            return false;
+        }
+
+        let run = chars[at..].iter()
+            .take(max)
+            .take_while(|c| self.matches_atom(atom, **c))
+            .count();
+        let matched = (min..=run).rev().any(|n| self.match_from(item + 1, at + n, chars, failed));
+        failed[key] = !matched;
+        matched
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> anyhow::Result<Pattern> {
+        let mut ranges = Vec::new();
+        let mut items = Vec::new();
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            let atom = match c {
+                '.' => Atom::Any,
+                '[' => {
+                    let start = ranges.len();
+                    let negated = chars.peek() == Some(&'^');
+                    if negated {
+                        chars.next();
+                    }
+                    loop {
+                        let lo = match chars.next() {
+                            Some(']') if ranges.len() > start => break,
+                            Some('\\') => chars.next(),
+                            c => c,
+                        }.ok_or_else(|| anyhow!("unterminated `[` in pattern {:?}", source))?;
+                        let hi = if chars.peek() == Some(&'-') {
+                            chars.next();
+                            match chars.next() {
+                                Some('\\') => chars.next(),
+                                c => c,
+                            }.ok_or_else(|| anyhow!("unterminated `[` in pattern {:?}", source))?
+                        } else {
+                            lo
+                        };
+                        if hi < lo {
+                            Err(anyhow!("invalid range {}-{} in pattern {:?}", lo, hi, source))?;
+                        }
+                        ranges.push((lo, hi));
+                    }
+                    Atom::Class(negated, start, ranges.len())
+                },
+                '?' | '*' | '+' | '{' | ']' | '}' => Err(anyhow!("unexpected `{}` in pattern {:?}", c, source))?,
+                c => {
+                    let c = if c == '\\' {
+                        chars.next().ok_or_else(|| anyhow!("trailing `\\` in pattern {:?}", source))?
+                    } else {
+                        c
+                    };
+                    ranges.push((c, c));
+                    Atom::Class(false, ranges.len() - 1, ranges.len())
+                },
+            };
+
+            let (min, max) = match chars.peek() {
+                Some('?') => (0, 1),
+                Some('*') => (0, usize::MAX),
+                Some('+') => (1, usize::MAX),
+                Some('{') => {
+                    chars.next();
+                    let mut spec = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => spec.push(c),
+                            None => Err(anyhow!("unterminated `{{` in pattern {:?}", source))?,
+                        }
+                    }
+                    let bad = || anyhow!("invalid repeat {{{}}} in pattern {:?}", spec, source);
+                    let (min, max) = match spec.find(',') {
+                        Some(idx) => (spec[..idx].parse().map_err(|_| bad())?, spec[idx + 1..].parse().map_err(|_| bad())?),
+                        None => {
+                            let n = spec.parse().map_err(|_| bad())?;
+                            (n, n)
+                        },
+                    };
+                    if max < min {
+                        Err(bad())?;
+                    }
+                    items.push((atom, min, max));
+                    continue;
+                },
+                _ => (1, 1),
+            };
+            if (min, max) != (1, 1) {
+                chars.next();
+            }
+            items.push((atom, min, max));
+        }
+
+        Ok(Pattern{
+            source: source.to_string(),
+            ranges,
+            items,
+        })
+    }
+}
+
+impl TryFrom<String> for Pattern {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Pattern> {
+        s.parse()
+    }
+}
+
+impl From<Pattern> for String {
+    fn from(p: Pattern) -> String {
+        p.source
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Check {
+    // An integer within the range.
+    Range(Range),
+    // An integer followed by one of the units, within that unit's range.
+    Units(BTreeMap<String, Range>),
+    Pattern(Pattern),
+    OneOf(Vec<String>),
+}
+
+impl Check {
+    // Checks a value, failing with the kind of problem and a description of
+    // what was expected.
+    pub fn check(&self, value: &str) -> Result<(), (ErrorKind, String)> {
+        match self {
+            Check::Range(range) => range.check(value, ""),
+            Check::Units(units) => {
+                let names = units.keys().map(String::as_str).collect::<Vec<_>>().join(" or ");
+                let idx = value.find(|c: char| !c.is_ascii_digit() && c != '-')
+                    .ok_or_else(|| (ErrorKind::BadUnit, names.clone()))?;
+                let (num, unit) = value.split_at(idx);
+                let range = units.get(unit)
+                    .ok_or((ErrorKind::BadUnit, names))?;
+                range.check(num, unit)
+            },
+            Check::Pattern(pattern) => {
+                if !pattern.is_match(value) {
+                    Err((ErrorKind::BadFormat, pattern.as_str().to_string()))?;
+                }
+                Ok(())
+            },
+            Check::OneOf(values) => {
+                if !values.iter().any(|v| v == value) {
+                    Err((ErrorKind::NotInSet, format!("one of {}", values.join(", "))))?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorKind {
+    OutOfRange,
+    BadUnit,
+    BadFormat,
+    NotInSet,
+    Missing,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: String,
+    pub kind: ErrorKind,
+    // The offending value, unless it is missing.
+    pub value: Option<String>,
+    pub expected: String,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.value.as_deref().unwrap_or("");
+        match self.kind {
+            ErrorKind::Missing => write!(f, "{} is missing", self.field)?,
+            ErrorKind::OutOfRange => write!(f, "{} {} is out of range", self.field, value)?,
+            ErrorKind::BadUnit => write!(f, "{} {} has a bad unit", self.field, value)?,
+            ErrorKind::BadFormat => write!(f, "{} {:?} is badly formatted", self.field, value)?,
+            ErrorKind::NotInSet => write!(f, "{} {:?} is not allowed", self.field, value)?,
+        }
+        write!(f, ", expected {}", self.expected)
+    }
+}
+
+// Every rule a record breaks, in the order of the rules.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationReport {
+    pub errors: Vec<FieldError>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_valid() {
+            return write!(f, "valid");
+        }
+        for (idx, e) in self.errors.iter().enumerate() {
+            if idx > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationReport {}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rule {
+    pub field: String,
+    #[serde(flatten)]
+    pub check: Check,
+}
+
+// Rules applied in order, each to the field it names. A field with a rule
+// must be present.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Rules {
+    pub rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn from_json(json: &str) -> anyhow::Result<Rules> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Rules> {
+        Rules::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    // Checks every rule, reporting each one which is broken. A missing field
+    // is reported once, however many rules it has.
+    pub fn validate(&self, record: &dyn Fields) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        for rule in self.rules.iter() {
+            let error = |kind, value, expected| FieldError{
+                field: rule.field.clone(),
+                kind,
+                value,
+                expected,
+            };
+
+            match record.field(&rule.field) {
+                None => {
+                    if !report.errors.iter().any(|e| e.field == rule.field && e.kind == ErrorKind::Missing) {
+                        report.errors.push(error(ErrorKind::Missing, None, "a value".to_string()));
+                    }
+                },
+                Some(value) => if let Err((kind, expected)) = rule.check.check(&value) {
+                    report.errors.push(error(kind, Some(value), expected));
+                },
+            }
+        }
+        report
+    }
+}
+
+#[test]
+fn test_rules() {
+    let hcl: Pattern = "#[0-9a-f]{6}".parse().unwrap();
+    assert!(hcl.is_match("#123abc"));
+    assert!(!hcl.is_match("#123abz"));
+    assert!(!hcl.is_match("123abc"));
+    assert!(!hcl.is_match("#123abcd"));
+
+    let p: Pattern = "a.*b+[^x-z\\]]?c{1,3}\\.".parse().unwrap();
+    assert!(p.is_match("abc."));
+    assert!(p.is_match("axxbbbwccc."));
+    assert!(p.is_match("abbbbc."));
+    assert!(!p.is_match("abxc."));
+    assert!(!p.is_match("abccccc."));
+    assert!(!p.is_match("ab]c."));
+    let slow: Pattern = "a*a*a*a*a*a*a*a*a*a*b".parse().unwrap();
+    assert!(!slow.is_match(&"a".repeat(200)));
+    assert!(slow.is_match(&("a".repeat(200) + "b")));
+    assert!("[a".parse::<Pattern>().is_err());
+    assert!("a{2,1}".parse::<Pattern>().is_err());
+    assert!("*".parse::<Pattern>().is_err());
+
+    struct Record(Vec<(&'static str, &'static str)>);
+    impl Fields for Record {
+        fn field(&self, name: &str) -> Option<String> {
+            self.0.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+        }
+    }
+
+    let rules = Rules::from_json(r#"[
+        { "field": "year", "range": { "min": 1920, "max": 2002 } },
+        { "field": "height", "units": { "cm": { "min": 150, "max": 193 } } },
+        { "field": "colour", "one_of": ["red", "blue"] },
+        { "field": "id", "pattern": "[0-9]{3}" }
+    ]"#).unwrap();
+    assert!(rules.validate(&Record(vec![("year", "2000"), ("height", "150cm"), ("colour", "red"), ("id", "012")])).is_valid());
+
+    let report = rules.validate(&Record(vec![("year", "2003"), ("height", "60in"), ("colour", "green"), ("id", "12a")]));
+    let kinds = report.errors.iter().map(|e| (e.field.as_str(), e.kind, e.value.as_deref())).collect::<Vec<_>>();
+    assert_eq!(kinds, vec![
+        ("year", ErrorKind::OutOfRange, Some("2003")),
+        ("height", ErrorKind::BadUnit, Some("60in")),
+        ("colour", ErrorKind::NotInSet, Some("green")),
+        ("id", ErrorKind::BadFormat, Some("12a")),
+    ]);
+    assert_eq!(report.to_string(), "year 2003 is out of range, expected 1920 to 2002; \
+height 60in has a bad unit, expected cm; \
+colour \"green\" is not allowed, expected one of red, blue; \
+id \"12a\" is badly formatted, expected [0-9]{3}");
+
+    let report = rules.validate(&Record(vec![("year", "x"), ("height", "194cm")]));
+    assert_eq!(report.errors.iter().map(|e| (e.kind, e.expected.as_str())).collect::<Vec<_>>(), vec![
+        (ErrorKind::BadFormat, "an integer"),
+        (ErrorKind::OutOfRange, "150 to 193cm"),
+        (ErrorKind::Missing, "a value"),
+        (ErrorKind::Missing, "a value"),
+    ]);
+    assert_eq!(report.errors[2].to_string(), "colour is missing, expected a value");
+    let report = rules.validate(&Record(vec![("year", "2000"), ("height", "1-2cm"), ("colour", "red"), ("id", "012")]));
+    assert_eq!(report.errors[0].expected, "an integer cm");
+
+    assert_eq!(Rules::from_json(&serde_json::to_string(&rules).unwrap()).unwrap(), rules);
+    assert!(Rules::from_json(r#"[{ "field": "x", "pattern": "[" }]"#).is_err());
+    assert!(Rules::from_json(r#"[{ "field": "x", "between": 1 }]"#).is_err());
+}
@@ -0,0 +1,235 @@
+use std::collections::{BTreeMap, VecDeque};
+use super::vm::{VM, Channels, ChannelBlocked, WouldBlock, DEFAULT_FUEL};
+
+// Numbered channels shared by every machine, each holding at most
+// `capacity` messages.
+#[derive(Debug, Clone, Default)]
+pub struct Bus {
+    capacity: usize,
+    queues: BTreeMap<i32, VecDeque<i32>>,
+}
+
+impl Bus {
+    pub fn new(capacity: usize) -> Bus {
+        Bus{
+            capacity,
+            queues: BTreeMap::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize { self.capacity }
+
+    pub fn pending(&self, channel: i32) -> usize {
+        self.queues.get(&channel).map_or(0, VecDeque::len)
+    }
+}
+
+impl Channels for Bus {
+    fn send(&mut self, channel: i32, value: i32) -> Result<(), ChannelBlocked> {
+        let queue = self.queues.entry(channel).or_default();
+        if queue.len() >= self.capacity {
+            return Err(ChannelBlocked::Full(channel));
+        }
+        queue.push_back(value);
+        Ok(())
+    }
+
+    fn recv(&mut self, channel: i32) -> Result<i32, ChannelBlocked> {
+        self.queues.get_mut(&channel)
+            .and_then(VecDeque::pop_front)
+            .ok_or(ChannelBlocked::Empty(channel))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Wait {
+    Input(i32),
+    Send(i32),
+    Recv(i32),
+}
+
+impl Wait {
+    fn from_error(e: &anyhow::Error) -> Option<Wait> {
+        if let Some(WouldBlock { port }) = e.downcast_ref() {
+            return Some(Wait::Input(*port));
+        }
+        e.downcast_ref().map(|b| match *b {
+            ChannelBlocked::Full(channel) => Wait::Send(channel),
+            ChannelBlocked::Empty(channel) => Wait::Recv(channel),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScheduleOutcome {
+    AllTerminated,
+    // Every machine that has not terminated is waiting, and none can move.
+    Deadlock {
+        waiting: Vec<(usize, Wait)>,
+    },
+    OutOfFuel,
+    // A machine failed with an error other than blocking, leaving the
+    // others where they stopped.
+    Faulted {
+        machine: usize,
+        error: String,
+    },
+}
+
+// Runs machines in turn, each for up to `quantum` steps or until it blocks,
+// so a run is deterministic for a given set of programs and inputs.
+pub struct Scheduler {
+    machines: Vec<VM>,
+    bus: Bus,
+    quantum: usize,
+    steps: u64,
+}
+
+impl Scheduler {
+    pub fn new(capacity: usize) -> Scheduler {
+        Scheduler{
+            machines: Vec::new(),
+            bus: Bus::new(capacity),
+            quantum: 1,
+            steps: 0,
+        }
+    }
+
+    pub fn set_quantum(&mut self, quantum: usize) {
+        self.quantum = quantum.max(1);
+    }
+
+    pub fn add(&mut self, vm: VM) -> usize {
+        self.machines.push(vm);
+        self.machines.len() - 1
+    }
+
+    pub fn machines(&self) -> &[VM] { &self.machines }
+
+    pub fn machine_mut(&mut self, idx: usize) -> &mut VM { &mut self.machines[idx] }
+
+    pub fn bus(&self) -> &Bus { &self.bus }
+
+    pub fn bus_mut(&mut self) -> &mut Bus { &mut self.bus }
+
+    // Total steps executed across all machines.
+    pub fn steps(&self) -> u64 { self.steps }
+
+    // Loops across machines aren't detected, so this always stops after
+    // `DEFAULT_FUEL` steps.
+    pub fn run(&mut self) -> ScheduleOutcome {
+        self.run_with_fuel(DEFAULT_FUEL as u64)
+    }
+
+    pub fn run_with_fuel(&mut self, mut fuel: u64) -> ScheduleOutcome {
+        loop {
+            let mut progressed = false;
+            let mut waiting = Vec::new();
+
+            for (idx, vm) in self.machines.iter_mut().enumerate() {
+                for _ in 0..self.quantum {
+                    if vm.is_terminated() {
+                        break;
+                    }
+
+                    if fuel == 0 {
+                        return ScheduleOutcome::OutOfFuel;
+                    }
+                    fuel -= 1;
+
+                    match vm.step_with(&mut self.bus) {
+                        Ok(()) => {
+                            progressed = true;
+                            self.steps += 1;
+                        },
+                        Err(e) => match Wait::from_error(&e) {
+                            Some(wait) => {
+                                fuel += 1;
+                                waiting.push((idx, wait));
+                                break;
+                            },
+                            None => return ScheduleOutcome::Faulted{ machine: idx, error: e.to_string() },
+                        },
+                    }
+                }
+            }
+
+            if self.machines.iter().all(VM::is_terminated) {
+                return ScheduleOutcome::AllTerminated;
+            }
+
+            if !progressed {
+                return ScheduleOutcome::Deadlock { waiting };
+            }
+        }
+    }
+}
+
+#[test]
+fn test_scheduler() {
+    use super::asm::parse_asm;
+
+    // A producer sends 1..=5 through a channel of capacity 2 to a consumer
+    // which sums them and reports the total on channel 1.
+    let producer = parse_asm("
+        mov a, 1
+    loop:
+        send 0, a
+        add a, 1
+        jlt a, 6, loop
+        recv b, 1
+        out 0, b
+    ").unwrap();
+    let consumer = parse_asm("
+        mov c, 5
+    loop:
+        recv a, 0
+        add acc, a
+        add c, -1
+        jnz c, loop
+        send 1, acc
+    ").unwrap();
+
+    // Any quantum gives the same result.
+    for quantum in [1, 3, 100].iter() {
+        let mut scheduler = Scheduler::new(2);
+        scheduler.set_quantum(*quantum);
+        let p = scheduler.add(VM::new(producer.clone(), Default::default()));
+        let c = scheduler.add(VM::new(consumer.clone(), Default::default()));
+        assert_eq!(scheduler.run(), ScheduleOutcome::AllTerminated);
+        assert_eq!(scheduler.machines()[p].output(0), &[15]);
+        assert_eq!(scheduler.machines()[c].state().acc, 15);
+        assert_eq!(scheduler.steps(), 18 + 22);
+    }
+
+    // Two machines each waiting on the other.
+    let mut scheduler = Scheduler::new(1);
+    scheduler.add(VM::new(parse_asm("recv a, 0\nsend 1, a").unwrap(), Default::default()));
+    scheduler.add(VM::new(parse_asm("recv a, 1\nsend 0, a").unwrap(), Default::default()));
+    scheduler.add(VM::new(parse_asm("in a, 3").unwrap(), Default::default()));
+    assert_eq!(scheduler.run(), ScheduleOutcome::Deadlock {
+        waiting: vec![(0, Wait::Recv(0)), (1, Wait::Recv(1)), (2, Wait::Input(3))],
+    });
+
+    // A full channel with no reader.
+    let mut scheduler = Scheduler::new(1);
+    scheduler.add(VM::new(parse_asm("send 0, 1\nsend 0, 2").unwrap(), Default::default()));
+    assert_eq!(scheduler.run(), ScheduleOutcome::Deadlock { waiting: vec![(0, Wait::Send(0))] });
+    assert_eq!(scheduler.bus().pending(0), 1);
+
+    let mut scheduler = Scheduler::new(1);
+    scheduler.add(VM::new(parse_asm("jmp +0").unwrap(), Default::default()));
+    assert_eq!(scheduler.run_with_fuel(100), ScheduleOutcome::OutOfFuel);
+    assert_eq!(scheduler.steps(), 100);
+
+    // One machine overflowing stops the run without touching the others.
+    let mut scheduler = Scheduler::new(1);
+    scheduler.add(VM::new(parse_asm("recv a, 0").unwrap(), Default::default()));
+    scheduler.add(VM::new(parse_asm("acc +2147483647\nacc +1").unwrap(), Default::default()));
+    assert_eq!(scheduler.run(), ScheduleOutcome::Faulted{ machine: 1, error: "acc overflowed i32".to_string() });
+    assert_eq!(scheduler.machines()[0].state().ip, 0);
+    assert_eq!(scheduler.machines()[1].state().ip, 1);
+
+    let mut vm = VM::new(parse_asm("send 0, 1").unwrap(), Default::default());
+    assert!(vm.step().is_err());
+}
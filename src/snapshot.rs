@@ -0,0 +1,296 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use anyhow::anyhow;
+use serde::{Serialize, Deserialize};
+use super::vm::{Instruction, State, HistoryEntry, Undo, Arithmetic};
+use super::codec::{Encoder, Decoder};
+
+const MAGIC: &[u8; 4] = b"VMSN";
+const VERSION: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotOptions {
+    pub memory: bool,
+    pub history: bool,
+}
+
+impl Default for SnapshotOptions {
+    fn default() -> SnapshotOptions {
+        SnapshotOptions{
+            memory: true,
+            history: true,
+        }
+    }
+}
+
+// Memory is mostly zeroes, so only nonzero cells are kept.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SparseMemory {
+    pub size: usize,
+    pub cells: Vec<(usize, i32)>,
+}
+
+// Everything needed to resume a run with `VM::from_snapshot`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub program: Vec<Instruction>,
+    pub state: State,
+    pub memory: Option<SparseMemory>,
+    pub inputs: BTreeMap<i32, VecDeque<i32>>,
+    pub outputs: BTreeMap<i32, Vec<i32>>,
+    pub history: Option<Vec<HistoryEntry>>,
+    pub profile: Option<Vec<u64>>,
+    #[serde(default)]
+    pub arithmetic: Arithmetic,
+}
+
+impl Snapshot {
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(input: &str) -> anyhow::Result<Snapshot> {
+        Ok(serde_json::from_str(input)?)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut e = Encoder::new();
+        e.bytes(MAGIC);
+        e.u8(VERSION);
+        e.instructions(&self.program);
+        e.arithmetic(self.arithmetic);
+        e.state(&self.state);
+
+        e.bool(self.memory.is_some());
+        if let Some(memory) = self.memory.as_ref() {
+            e.usize(memory.size);
+            e.usize(memory.cells.len());
+            for &(addr, value) in memory.cells.iter() {
+                e.usize(addr);
+                e.i32(value);
+            }
+        }
+
+        e.usize(self.inputs.len());
+        for (port, values) in self.inputs.iter() {
+            e.i32(*port);
+            e.usize(values.len());
+            for v in values.iter() {
+                e.i32(*v);
+            }
+        }
+
+        e.usize(self.outputs.len());
+        for (port, values) in self.outputs.iter() {
+            e.i32(*port);
+            e.usize(values.len());
+            for v in values.iter() {
+                e.i32(*v);
+            }
+        }
+
+        e.bool(self.history.is_some());
+        if let Some(history) = self.history.as_ref() {
+            e.usize(history.len());
+            for entry in history.iter() {
+                e.state(&entry.state);
+                match entry.undo {
+                    Undo::None => e.u8(0),
+                    Undo::Memory { addr, old } => {
+                        e.u8(1);
+                        e.usize(addr);
+                        e.i32(old);
+                    },
+                    Undo::Input { port, value } => {
+                        e.u8(2);
+                        e.i32(port);
+                        e.i32(value);
+                    },
+                    Undo::Output { port } => {
+                        e.u8(3);
+                        e.i32(port);
+                    },
+                    Undo::Channel { channel } => {
+                        e.u8(4);
+                        e.i32(channel);
+                    },
+                }
+            }
+        }
+
+        e.bool(self.profile.is_some());
+        if let Some(profile) = self.profile.as_ref() {
+            e.usize(profile.len());
+            for count in profile.iter() {
+                e.uvarint(*count);
+            }
+        }
+
+        e.finish()
+    }
+
+    pub fn from_bytes(input: &[u8]) -> anyhow::Result<Snapshot> {
+        let mut d = Decoder::new(input);
+        if d.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            Err(anyhow!("not a vm snapshot"))?;
+        }
+        let version = d.u8()?;
+        if version != VERSION {
+            Err(anyhow!("unsupported snapshot version {}", version))?;
+        }
+
+        let program = d.instructions()?;
+        let arithmetic = d.arithmetic()?;
+        let state = d.state()?;
+
+        let memory = if d.bool()? {
+            let size = d.usize()?;
+            let len = d.len(2)?;
+            let cells = (0..len)
+                .map(|_| Ok((d.usize()?, d.i32()?)))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Some(SparseMemory{ size, cells })
+        } else {
+            None
+        };
+
+        let mut inputs = BTreeMap::new();
+        for _ in 0..d.len(2)? {
+            let port = d.i32()?;
+            let len = d.len(1)?;
+            let values = (0..len).map(|_| d.i32()).collect::<anyhow::Result<_>>()?;
+            inputs.insert(port, values);
+        }
+
+        let mut outputs = BTreeMap::new();
+        for _ in 0..d.len(2)? {
+            let port = d.i32()?;
+            let len = d.len(1)?;
+            let values = (0..len).map(|_| d.i32()).collect::<anyhow::Result<_>>()?;
+            outputs.insert(port, values);
+        }
+
+        let history = if d.bool()? {
+            let len = d.len(7)?;
+            let mut history = Vec::with_capacity(len);
+            for _ in 0..len {
+                let state = d.state()?;
+                let undo = match d.u8()? {
+                    0 => Undo::None,
+                    1 => Undo::Memory { addr: d.usize()?, old: d.i32()? },
+                    2 => Undo::Input { port: d.i32()?, value: d.i32()? },
+                    3 => Undo::Output { port: d.i32()? },
+                    4 => Undo::Channel { channel: d.i32()? },
+                    x => Err(anyhow!("invalid undo tag {}", x))?,
+                };
+                history.push(HistoryEntry{ state, undo });
+            }
+            Some(history)
+        } else {
+            None
+        };
+
+        let profile = if d.bool()? {
+            let len = d.len(1)?;
+            Some((0..len).map(|_| d.uvarint()).collect::<anyhow::Result<_>>()?)
+        } else {
+            None
+        };
+
+        if !d.is_empty() {
+            Err(anyhow!("trailing data after snapshot"))?;
+        }
+
+        Ok(Snapshot{
+            program,
+            state,
+            memory,
+            inputs,
+            outputs,
+            history,
+            profile,
+            arithmetic,
+        })
+    }
+
+    // Files ending in `.json` are written as JSON, anything else in the
+    // binary format.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if path.extension().is_some_and(|e| e == "json") {
+            std::fs::write(path, self.to_json()?)?;
+        } else {
+            std::fs::write(path, self.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    // Either format is accepted, told apart by the binary header.
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Snapshot> {
+        let contents = std::fs::read(path)?;
+        if contents.starts_with(MAGIC) {
+            Snapshot::from_bytes(&contents)
+        } else {
+            Snapshot::from_json(std::str::from_utf8(&contents)?)
+        }
+    }
+}
+
+#[test]
+fn test_snapshot() {
+    use super::vm::{VM, Register, Operand};
+    use Instruction::*;
+
+    let program = vec![
+        In(Register::A, 0),
+        Store(Operand::Imm(7), Operand::Reg(Register::A)),
+        Out(1, Operand::Reg(Register::A)),
+        Acc(-3),
+        Jmp(-4),
+    ];
+    let mut vm = VM::new(program, Default::default());
+    vm.set_recording(true);
+    vm.set_profiling(true);
+    for v in [5, 6, 7].iter() {
+        vm.push_input(0, *v);
+    }
+    for _ in 0..7 {
+        vm.step().unwrap();
+    }
+
+    let snapshot = vm.snapshot(&SnapshotOptions::default());
+    assert_eq!(snapshot.memory.as_ref().unwrap().cells, vec![(7, 6)]);
+    assert_eq!(Snapshot::from_json(&snapshot.to_json().unwrap()).unwrap(), snapshot);
+    assert_eq!(Snapshot::from_bytes(&snapshot.to_bytes()).unwrap(), snapshot);
+
+    // A resumed VM continues exactly as the original would have.
+    let mut resumed = VM::from_snapshot(Snapshot::from_bytes(&snapshot.to_bytes()).unwrap()).unwrap();
+    for _ in 0..5 {
+        vm.step().unwrap();
+        resumed.step().unwrap();
+    }
+    assert_eq!(resumed.state(), vm.state());
+    assert_eq!(resumed.output(1), &[5, 6]);
+    assert_eq!(resumed.memory(), vm.memory());
+    resumed.goto_step(2).unwrap();
+    assert_eq!(resumed.memory()[7], 5);
+
+    let bare = vm.snapshot(&SnapshotOptions{ memory: false, history: false });
+    assert_eq!(Snapshot::from_bytes(&bare.to_bytes()).unwrap(), bare);
+    assert!(!VM::from_snapshot(bare).unwrap().is_recording());
+
+    // Snapshots from corrupt files are rejected rather than trusted.
+    let mut huge = snapshot.clone();
+    huge.memory.as_mut().unwrap().size = usize::MAX;
+    assert!(VM::from_snapshot(huge).is_err());
+    let mut bad_undo = snapshot.clone();
+    bad_undo.history.as_mut().unwrap()[1].undo = Undo::Memory { addr: 4096, old: 0 };
+    assert!(VM::from_snapshot(bad_undo).is_err());
+    let mut bad_ip = snapshot.clone();
+    bad_ip.history.as_mut().unwrap()[0].state.ip = 5;
+    assert!(VM::from_snapshot(bad_ip).is_err());
+
+    let bytes = snapshot.to_bytes();
+    assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    assert!(Snapshot::from_bytes(b"VMSN\x01").is_err());
+}
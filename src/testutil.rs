@@ -0,0 +1,23 @@
+// A linear congruential generator for randomised tests, so that every run
+// sees the same programs and a failure reproduces from its seed.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng{ state: seed }
+    }
+
+    // The low bits of an LCG cycle quickly, so callers should prefer the
+    // high ones.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        self.state
+    }
+
+    // A value in `0..n`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        (self.next_u64() >> 33) % n
+    }
+}
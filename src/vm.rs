@@ -1,42 +1,518 @@
-#[derive(Copy, Clone, Debug)]
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use super::snapshot::{Snapshot, SnapshotOptions, SparseMemory};
+
+pub const DEFAULT_MEMORY_SIZE: usize = 4096;
+
+// The largest memory a snapshot may ask for, so a corrupt one can't exhaust
+// the host's.
+pub const MAX_MEMORY_SIZE: usize = 1 << 24;
+
+// The step budget `VM::run` gives programs whose loops it can't detect.
+pub const DEFAULT_FUEL: usize = 10_000_000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Register {
+    Acc,
+    A,
+    B,
+    C,
+    D,
+}
+
+impl Register {
+    pub const ALL: [Register; 5] = [Register::Acc, Register::A, Register::B, Register::C, Register::D];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Register::Acc => "acc",
+            Register::A => "a",
+            Register::B => "b",
+            Register::C => "c",
+            Register::D => "d",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Register> {
+        Register::ALL.iter().copied().find(|r| r.name() == name)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Operand {
+    Imm(i32),
+    Reg(Register),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Imm(x) => x.fmt(f),
+            Operand::Reg(r) => f.write_str(r.name()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Instruction {
     Acc(i32),
     Jmp(i32),
     Nop(i32),
+    Mov(Register, Operand),
+    Add(Register, Operand),
+    Mul(Register, Operand),
+    Mod(Register, Operand),
+    Load(Register, Operand),
+    Store(Operand, Operand),
+    Jz(Operand, i32),
+    Jnz(Operand, i32),
+    Jlt(Operand, Operand, i32),
+    In(Register, i32),
+    Out(i32, Operand),
+    Send(i32, Operand),
+    Recv(Register, i32),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Width {
+    I32,
+    I64,
+    I128,
+}
+
+impl Width {
+    pub fn bounds(&self) -> (i128, i128) {
+        match self {
+            Width::I32 => (i32::MIN as i128, i32::MAX as i128),
+            Width::I64 => (i64::MIN as i128, i64::MAX as i128),
+            Width::I128 => (i128::MIN, i128::MAX),
+        }
+    }
+
+    fn wrap(&self, value: i128) -> i128 {
+        match self {
+            Width::I32 => value as i32 as i128,
+            Width::I64 => value as i64 as i128,
+            Width::I128 => value,
+        }
+    }
+}
+
+impl fmt::Display for Width {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Width::I32 => "i32",
+            Width::I64 => "i64",
+            Width::I128 => "i128",
+        })
+    }
+}
+
+impl std::str::FromStr for Width {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Width> {
+        Ok(match s {
+            "i32" => Width::I32,
+            "i64" => Width::I64,
+            "i128" => Width::I128,
+            _ => Err(anyhow::anyhow!("unknown width: {}", s))?,
+        })
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Overflow {
+    Wrapping,
+    Saturating,
+    Checked,
+}
+
+impl std::str::FromStr for Overflow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Overflow> {
+        Ok(match s {
+            "wrapping" => Overflow::Wrapping,
+            "saturating" => Overflow::Saturating,
+            "checked" => Overflow::Checked,
+            _ => Err(anyhow::anyhow!("unknown overflow policy: {}", s))?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverflowError {
+    pub register: Register,
+    pub width: Width,
+}
+
+impl fmt::Display for OverflowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} overflowed {}", self.register.name(), self.width)
+    }
+}
+
+impl std::error::Error for OverflowError {}
+
+// How arithmetic behaves. acc holds `width` bits while the general registers,
+// memory and ports are always 32 bits wide; the overflow policy applies to
+// every operation, including moving acc into a narrower location.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Arithmetic {
+    pub width: Width,
+    pub overflow: Overflow,
+}
+
+impl Default for Arithmetic {
+    fn default() -> Arithmetic {
+        Arithmetic{
+            width: Width::I32,
+            overflow: Overflow::Checked,
+        }
+    }
+}
+
+impl Arithmetic {
+    pub fn width_of(&self, r: Register) -> Width {
+        match r {
+            Register::Acc => self.width,
+            _ => Width::I32,
+        }
+    }
+
+    // Fits the exact result of an operation into `r`. `exact` is None when
+    // even i128 overflowed, in which case `wrapped` and `negative` describe
+    // the wrapped result and the sign of the true one.
+    fn fit(&self, r: Register, exact: Option<i128>, wrapped: i128, negative: bool) -> anyhow::Result<i128> {
+        let width = self.width_of(r);
+        let (min, max) = width.bounds();
+        Ok(match (self.overflow, exact) {
+            (_, Some(v)) if v >= min && v <= max => v,
+            (Overflow::Wrapping, _) => width.wrap(wrapped),
+            (Overflow::Saturating, Some(v)) => v.clamp(min, max),
+            (Overflow::Saturating, None) => if negative { min } else { max },
+            (Overflow::Checked, _) => Err(OverflowError{ register: r, width })?,
+        })
+    }
+
+    pub fn narrow(&self, r: Register, value: i128) -> anyhow::Result<i128> {
+        self.fit(r, Some(value), value, value < 0)
+    }
+
+    pub fn add(&self, r: Register, a: i128, b: i128) -> anyhow::Result<i128> {
+        self.fit(r, a.checked_add(b), a.wrapping_add(b), a < 0)
+    }
+
+    pub fn mul(&self, r: Register, a: i128, b: i128) -> anyhow::Result<i128> {
+        self.fit(r, a.checked_mul(b), a.wrapping_mul(b), (a < 0) != (b < 0))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct State {
     pub ip: i32,
-    pub acc: i32,
+    pub acc: i128,
+    pub regs: [i32; 4],
 }
 
-impl Default for State {
-    fn default() -> State {
-        State{
-            ip: 0,
-            acc: 0,
+impl State {
+    pub fn register(&self, r: Register) -> i128 {
+        match r {
+            Register::Acc => self.acc,
+            Register::A => self.regs[0] as i128,
+            Register::B => self.regs[1] as i128,
+            Register::C => self.regs[2] as i128,
+            Register::D => self.regs[3] as i128,
+        }
+    }
+
+    // The value must already fit the register, see `Arithmetic::narrow`.
+    pub fn set_register(&mut self, r: Register, value: i128) {
+        match r {
+            Register::Acc => self.acc = value,
+            Register::A => self.regs[0] = value as i32,
+            Register::B => self.regs[1] = value as i32,
+            Register::C => self.regs[2] = value as i32,
+            Register::D => self.regs[3] = value as i32,
+        }
+    }
+
+    pub fn read(&self, operand: Operand) -> i128 {
+        match operand {
+            Operand::Imm(x) => x as i128,
+            Operand::Reg(r) => self.register(r),
         }
     }
 }
 
+impl Instruction {
+    pub fn branch_offset(&self) -> Option<i32> {
+        match *self {
+            Instruction::Jmp(offset)
+            | Instruction::Jz(_, offset)
+            | Instruction::Jnz(_, offset)
+            | Instruction::Jlt(_, _, offset) => Some(offset),
+            _ => None,
+        }
+    }
+
+    // The same instruction branching by `offset` instead, if it branches.
+    pub fn with_branch_offset(&self, offset: i32) -> Instruction {
+        match *self {
+            Instruction::Jmp(_) => Instruction::Jmp(offset),
+            Instruction::Jz(x, _) => Instruction::Jz(x, offset),
+            Instruction::Jnz(x, _) => Instruction::Jnz(x, offset),
+            Instruction::Jlt(x, y, _) => Instruction::Jlt(x, y, offset),
+            inst => inst,
+        }
+    }
+
+    pub fn branch_target(&self, ip: i32) -> Option<i32> {
+        self.branch_offset().map(|offset| ip + offset)
+    }
+
+    pub fn is_conditional(&self) -> bool {
+        matches!(self, Instruction::Jz(..) | Instruction::Jnz(..) | Instruction::Jlt(..))
+    }
+
+    pub fn falls_through(&self) -> bool {
+        !matches!(self, Instruction::Jmp(_))
+    }
+
+    // The next ip for instructions whose control flow does not depend on
+    // data. Conditional branches report their fall-through.
+    pub fn successor(&self, ip: i32) -> i32 {
+        match self {
+            Instruction::Jmp(offset) => ip + offset,
+            _ => ip + 1,
+        }
+    }
+
+    pub fn flipped(&self) -> Option<Instruction> {
+        match *self {
+            Instruction::Nop(x) => Some(Instruction::Jmp(x)),
+            Instruction::Jmp(x) => Some(Instruction::Nop(x)),
+            _ => None,
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Acc(_) => "acc",
+            Instruction::Jmp(_) => "jmp",
+            Instruction::Nop(_) => "nop",
+            Instruction::Mov(..) => "mov",
+            Instruction::Add(..) => "add",
+            Instruction::Mul(..) => "mul",
+            Instruction::Mod(..) => "mod",
+            Instruction::Load(..) => "ld",
+            Instruction::Store(..) => "st",
+            Instruction::Jz(..) => "jz",
+            Instruction::Jnz(..) => "jnz",
+            Instruction::Jlt(..) => "jlt",
+            Instruction::In(..) => "in",
+            Instruction::Out(..) => "out",
+            Instruction::Send(..) => "send",
+            Instruction::Recv(..) => "recv",
+        }
+    }
+
+    // Writes the instruction as assembly, optionally replacing the branch
+    // offset with a label.
+    pub fn write_asm<W: fmt::Write>(&self, f: &mut W, label: Option<&str>) -> fmt::Result {
+        write!(f, "{} ", self.mnemonic())?;
+        match *self {
+            Instruction::Acc(x) | Instruction::Nop(x) => write!(f, "{:+}", x)?,
+            Instruction::Jmp(_) => {},
+            Instruction::Mov(r, x)
+            | Instruction::Add(r, x)
+            | Instruction::Mul(r, x)
+            | Instruction::Mod(r, x)
+            | Instruction::Load(r, x) => write!(f, "{}, {}", r.name(), x)?,
+            Instruction::Store(a, x) => write!(f, "{}, {}", a, x)?,
+            Instruction::Jz(x, _) | Instruction::Jnz(x, _) => write!(f, "{}, ", x)?,
+            Instruction::Jlt(x, y, _) => write!(f, "{}, {}, ", x, y)?,
+            Instruction::In(r, port) | Instruction::Recv(r, port) => write!(f, "{}, {}", r.name(), port)?,
+            Instruction::Out(port, x) | Instruction::Send(port, x) => write!(f, "{}, {}", port, x)?,
+        }
+
+        match (self.branch_offset(), label) {
+            (Some(_), Some(label)) => f.write_str(label),
+            (Some(offset), None) => write!(f, "{:+}", offset),
+            (None, _) => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_asm(f, None)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WouldBlock {
+    pub port: i32,
+}
+
+impl fmt::Display for WouldBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no input available on port {}", self.port)
+    }
+}
+
+impl std::error::Error for WouldBlock {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelBlocked {
+    Full(i32),
+    Empty(i32),
+}
+
+impl fmt::Display for ChannelBlocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelBlocked::Full(channel) => write!(f, "channel {} is full", channel),
+            ChannelBlocked::Empty(channel) => write!(f, "no message on channel {}", channel),
+        }
+    }
+}
+
+impl std::error::Error for ChannelBlocked {}
+
+// Where `send` and `recv` deliver messages, usually a `Scheduler`'s bus.
+pub trait Channels {
+    fn send(&mut self, channel: i32, value: i32) -> Result<(), ChannelBlocked>;
+    fn recv(&mut self, channel: i32) -> Result<i32, ChannelBlocked>;
+}
+
+// What a step changed outside of `State`, so that it can be reversed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Undo {
+    None,
+    Memory {
+        addr: usize,
+        old: i32,
+    },
+    Input {
+        port: i32,
+        value: i32,
+    },
+    Output {
+        port: i32,
+    },
+    // Messages belong to other machines, so these steps cannot be reversed.
+    Channel {
+        channel: i32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub state: State,
+    pub undo: Undo,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunOutcome {
+    Terminated(State),
+    InfiniteLoop {
+        ip: i32,
+        body: Vec<i32>,
+        state: State,
+    },
+    JumpedOutOfRange {
+        ip: i32,
+        state: State,
+    },
+    OutOfFuel(State),
+}
+
+impl RunOutcome {
+    pub fn state(&self) -> &State {
+        match self {
+            RunOutcome::Terminated(state) => state,
+            RunOutcome::InfiniteLoop { state, .. } => state,
+            RunOutcome::JumpedOutOfRange { state, .. } => state,
+            RunOutcome::OutOfFuel(state) => state,
+        }
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        matches!(self, RunOutcome::Terminated(_))
+    }
+}
+
 pub struct VM {
     instructions: Vec<Instruction>,
     state: State,
+    memory: Vec<i32>,
+    inputs: BTreeMap<i32, VecDeque<i32>>,
+    outputs: BTreeMap<i32, Vec<i32>>,
+    // When recording, the state before each executed step, indexed by step.
+    history: Option<Vec<HistoryEntry>>,
+    // When profiling, the number of times each ip has executed.
+    profile: Option<Vec<u64>>,
+    arithmetic: Arithmetic,
 }
 
 impl VM {
     pub fn new(instructions: Vec<Instruction>, state: State) -> VM {
+        VM::with_memory_size(instructions, state, DEFAULT_MEMORY_SIZE)
+    }
+
+    pub fn with_memory_size(instructions: Vec<Instruction>, state: State, memory_size: usize) -> VM {
         VM{
             instructions,
             state,
+            memory: vec![0; memory_size],
+            inputs: BTreeMap::new(),
+            outputs: BTreeMap::new(),
+            history: None,
+            profile: None,
+            arithmetic: Arithmetic::default(),
         }
     }
 
+    pub fn arithmetic(&self) -> Arithmetic { self.arithmetic }
+
+    // Changing the arithmetic applies the new policy to the current acc.
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) -> anyhow::Result<()> {
+        self.state.acc = arithmetic.narrow(Register::Acc, self.state.acc)?;
+        self.arithmetic = arithmetic;
+        Ok(())
+    }
+
     pub fn instructions(&self) -> &[Instruction] { &self.instructions }
 
     pub fn state(&self) -> &State { &self.state }
 
+    pub fn memory(&self) -> &[i32] { &self.memory }
+
+    pub fn push_input(&mut self, port: i32, value: i32) {
+        self.inputs.entry(port).or_default().push_back(value);
+    }
+
+    pub fn output(&self, port: i32) -> &[i32] {
+        self.outputs.get(&port).map_or(&[], Vec::as_slice)
+    }
+
+    // Stepping back can't put taken output back, so this clears the history.
+    pub fn take_output(&mut self, port: i32) -> Vec<i32> {
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
+        }
+        self.outputs.remove(&port).unwrap_or_default()
+    }
+
+    pub fn patch(&mut self, ip: usize, inst: Instruction) -> anyhow::Result<Instruction> {
+        let slot = self.instructions.get_mut(ip)
+            .ok_or_else(|| anyhow::Error::msg("ip out of range"))?;
+        Ok(std::mem::replace(slot, inst))
+    }
+
     fn get_instruction(&self, ip: i32) -> anyhow::Result<Instruction> {
         if ip < 0 || ip >= self.instructions.len() as i32 {
             Err(anyhow::Error::msg("ip out of range"))?;
@@ -45,17 +521,595 @@ impl VM {
         Ok(self.instructions[ip as usize])
     }
 
+    fn address(&self, operand: Operand) -> anyhow::Result<usize> {
+        let addr = self.state.read(operand);
+        if addr < 0 || addr >= self.memory.len() as i128 {
+            Err(anyhow::anyhow!("memory address {} out of range", addr))?;
+        }
+
+        Ok(addr as usize)
+    }
+
+    // Reads an operand for storage in a 32-bit location.
+    fn read_narrow(&self, operand: Operand) -> anyhow::Result<i32> {
+        let value = self.state.read(operand);
+        Ok(self.arithmetic.narrow(Register::A, value)? as i32)
+    }
+
     pub fn step(&mut self) -> anyhow::Result<()> {
+        self.step_inner(None)
+    }
+
+    // Steps with `send` and `recv` connected to `channels`. A blocked channel
+    // fails the step with `ChannelBlocked`, leaving the VM unchanged.
+    pub fn step_with(&mut self, channels: &mut dyn Channels) -> anyhow::Result<()> {
+        self.step_inner(Some(channels))
+    }
+
+    fn step_inner(&mut self, channels: Option<&mut dyn Channels>) -> anyhow::Result<()> {
         let inst = self.get_instruction(self.state.ip)?;
-        self.state.ip += 1;
+        let before = self.state.clone();
+        let arith = self.arithmetic;
+        let state = &mut self.state;
+        let mut next = state.ip + 1;
+        let mut undo = Undo::None;
 
         match inst {
-            Instruction::Acc(a) => self.state.acc += a,
-            Instruction::Jmp(offset) => self.state.ip += offset - 1,
+            Instruction::Acc(a) => state.acc = arith.add(Register::Acc, state.acc, a as i128)?,
+            Instruction::Jmp(offset) => next = state.ip.saturating_add(offset),
             Instruction::Nop(_) => {},
+            Instruction::Mov(r, x) => state.set_register(r, arith.narrow(r, state.read(x))?),
+            Instruction::Add(r, x) => state.set_register(r, arith.add(r, state.register(r), state.read(x))?),
+            Instruction::Mul(r, x) => state.set_register(r, arith.mul(r, state.register(r), state.read(x))?),
+            Instruction::Mod(r, x) => {
+                let divisor = state.read(x);
+                if divisor == 0 {
+                    Err(anyhow::Error::msg("modulo by zero"))?;
+                }
+                // Only MIN % -1 fails, and its remainder is zero.
+                let value = state.register(r).checked_rem_euclid(divisor).unwrap_or(0);
+                state.set_register(r, arith.narrow(r, value)?);
+            },
+            Instruction::Load(r, addr) => {
+                let addr = self.address(addr)?;
+                self.state.set_register(r, self.memory[addr] as i128);
+            },
+            Instruction::Store(addr, x) => {
+                let addr = self.address(addr)?;
+                let value = self.read_narrow(x)?;
+                let old = std::mem::replace(&mut self.memory[addr], value);
+                undo = Undo::Memory { addr, old };
+            },
+            Instruction::Jz(x, offset) => if state.read(x) == 0 {
+                next = state.ip.saturating_add(offset);
+            },
+            Instruction::Jnz(x, offset) => if state.read(x) != 0 {
+                next = state.ip.saturating_add(offset);
+            },
+            Instruction::Jlt(x, y, offset) => if state.read(x) < state.read(y) {
+                next = state.ip.saturating_add(offset);
+            },
+            Instruction::In(r, port) => {
+                let value = self.inputs.get_mut(&port)
+                    .and_then(VecDeque::pop_front)
+                    .ok_or(WouldBlock{ port })?;
+                self.state.set_register(r, value as i128);
+                undo = Undo::Input { port, value };
+            },
+            Instruction::Out(port, x) => {
+                let value = self.read_narrow(x)?;
+                self.outputs.entry(port).or_default().push(value);
+                undo = Undo::Output { port };
+            },
+            Instruction::Send(channel, x) => {
+                let value = self.read_narrow(x)?;
+                channels.ok_or_else(|| anyhow::Error::msg("send needs channels, see Scheduler"))?
+                    .send(channel, value)?;
+                undo = Undo::Channel { channel };
+            },
+            Instruction::Recv(r, channel) => {
+                let value = channels.ok_or_else(|| anyhow::Error::msg("recv needs channels, see Scheduler"))?
+                    .recv(channel)?;
+                self.state.set_register(r, value as i128);
+                undo = Undo::Channel { channel };
+            },
+        }
+
+        if let Some(profile) = self.profile.as_mut() {
+            profile[before.ip as usize] += 1;
+        }
+
+        self.state.ip = next;
+        if let Some(history) = self.history.as_mut() {
+            history.push(HistoryEntry{ state: before, undo });
         }
 
         Ok(())
     }
+
+    pub fn is_terminated(&self) -> bool {
+        self.state.ip == self.instructions.len() as i32
+    }
+
+    pub fn set_recording(&mut self, recording: bool) {
+        if !recording {
+            self.history = None;
+        } else if self.history.is_none() {
+            self.history = Some(Vec::new());
+        }
+    }
+
+    pub fn is_recording(&self) -> bool { self.history.is_some() }
+
+    pub fn set_profiling(&mut self, profiling: bool) {
+        if !profiling {
+            self.profile = None;
+        } else if self.profile.is_none() {
+            self.profile = Some(vec![0; self.instructions.len()]);
+        }
+    }
+
+    pub fn profile(&self) -> Option<&[u64]> { self.profile.as_deref() }
+
+    pub fn history(&self) -> &[HistoryEntry] {
+        self.history.as_deref().unwrap_or(&[])
+    }
+
+    // The number of steps recorded so far, which is also the current step.
+    pub fn step_count(&self) -> usize {
+        self.history().len()
+    }
+
+    fn history_mut(&mut self) -> anyhow::Result<&mut Vec<HistoryEntry>> {
+        self.history.as_mut()
+            .ok_or_else(|| anyhow::Error::msg("history is not being recorded"))
+    }
+
+    pub fn step_back(&mut self) -> anyhow::Result<()> {
+        if let Some(Undo::Channel { channel }) = self.history_mut()?.last().map(|e| &e.undo) {
+            Err(anyhow::anyhow!("cannot step back over a message on channel {}", channel))?;
+        }
+
+        let entry = self.history_mut()?.pop()
+            .ok_or_else(|| anyhow::Error::msg("already at the first recorded step"))?;
+        match entry.undo {
+            Undo::None => {},
+            Undo::Memory { addr, old } => self.memory[addr] = old,
+            Undo::Input { port, value } => self.inputs.entry(port).or_default().push_front(value),
+            Undo::Output { port } => {
+                self.outputs.get_mut(&port).and_then(Vec::pop);
+            },
+            Undo::Channel { .. } => unreachable!(),
+        }
+        self.state = entry.state;
+        Ok(())
+    }
+
+    pub fn goto_step(&mut self, step: usize) -> anyhow::Result<()> {
+        self.history_mut()?;
+        while self.step_count() > step {
+            self.step_back()?;
+        }
+
+        while self.step_count() < step {
+            self.step()?;
+        }
+
+        Ok(())
+    }
+
+    // Finds the most recent step that changed acc, returning the step number
+    // and the ip of the instruction that executed.
+    pub fn last_acc_change(&self) -> Option<(usize, i32)> {
+        let history = self.history();
+        (0..history.len()).rev()
+            .find(|&step| {
+                let after = history.get(step + 1).map_or(&self.state, |e| &e.state);
+                history[step].state.acc != after.acc
+            })
+            .map(|step| (step, history[step].state.ip))
+    }
+
+    pub fn snapshot(&self, options: &SnapshotOptions) -> Snapshot {
+        let memory = if options.memory {
+            Some(SparseMemory{
+                size: self.memory.len(),
+                cells: self.memory.iter()
+                    .copied()
+                    .enumerate()
+                    .filter(|(_, v)| *v != 0)
+                    .collect(),
+            })
+        } else {
+            None
+        };
+
+        Snapshot{
+            program: self.instructions.clone(),
+            state: self.state.clone(),
+            memory,
+            inputs: self.inputs.clone(),
+            outputs: self.outputs.clone(),
+            history: if options.history { self.history.clone() } else { None },
+            profile: self.profile.clone(),
+            arithmetic: self.arithmetic,
+        }
+    }
+
+    // Rebuilds a VM from a snapshot. Without saved memory the VM resumes with
+    // zeroed memory of the default size. The snapshot may come from a corrupt
+    // file, so anything a later step would trust is checked first.
+    pub fn from_snapshot(snapshot: Snapshot) -> anyhow::Result<VM> {
+        let size = snapshot.memory.as_ref().map_or(DEFAULT_MEMORY_SIZE, |m| m.size);
+        if size > MAX_MEMORY_SIZE {
+            Err(anyhow::anyhow!("snapshot memory size {} is over the limit of {}", size, MAX_MEMORY_SIZE))?;
+        }
+        let mut memory = vec![0; size];
+        for &(addr, value) in snapshot.memory.iter().flat_map(|m| m.cells.iter()) {
+            *memory.get_mut(addr)
+                .ok_or_else(|| anyhow::anyhow!("snapshot memory address {} out of range", addr))? = value;
+        }
+
+        if let Some(profile) = snapshot.profile.as_ref() {
+            if profile.len() != snapshot.program.len() {
+                Err(anyhow::Error::msg("snapshot profile does not match the program"))?;
+            }
+        }
+
+        for (step, entry) in snapshot.history.iter().flatten().enumerate() {
+            if entry.state.ip < 0 || entry.state.ip as usize >= snapshot.program.len() {
+                Err(anyhow::anyhow!("snapshot history step {} has ip {} out of range", step, entry.state.ip))?;
+            }
+            if let Undo::Memory { addr, .. } = entry.undo {
+                if addr >= memory.len() {
+                    Err(anyhow::anyhow!("snapshot history step {} has memory address {} out of range", step, addr))?;
+                }
+            }
+        }
+
+        Ok(VM{
+            instructions: snapshot.program,
+            state: snapshot.state,
+            memory,
+            inputs: snapshot.inputs,
+            outputs: snapshot.outputs,
+            history: snapshot.history,
+            profile: snapshot.profile,
+            arithmetic: snapshot.arithmetic,
+        })
+    }
+
+    // Always returns: loops are detected in programs without conditional
+    // branches, and other programs run out of `DEFAULT_FUEL` instead.
+    pub fn run(&mut self) -> anyhow::Result<RunOutcome> {
+        if self.detects_loops() {
+            self.run_inner(None)
+        } else {
+            self.run_inner(Some(DEFAULT_FUEL))
+        }
+    }
+
+    pub fn run_with_fuel(&mut self, fuel: usize) -> anyhow::Result<RunOutcome> {
+        self.run_inner(Some(fuel))
+    }
+
+    // Once branches depend on data a revisited ip no longer implies a loop.
+    fn detects_loops(&self) -> bool {
+        !self.instructions.iter().any(Instruction::is_conditional)
+    }
+
+    fn run_inner(&mut self, mut fuel: Option<usize>) -> anyhow::Result<RunOutcome> {
+        // The first step at which each ip was executed, so that a revisit can
+        // recover the loop body from the trace.
+        let detect_loops = self.detects_loops();
+        let mut first_seen = vec![None; self.instructions.len()];
+        let mut trace = Vec::new();
+
+        loop {
+            let ip = self.state.ip;
+            if self.is_terminated() {
+                return Ok(RunOutcome::Terminated(self.state.clone()));
+            }
+
+            if ip < 0 || ip > self.instructions.len() as i32 {
+                return Ok(RunOutcome::JumpedOutOfRange {
+                    ip,
+                    state: self.state.clone(),
+                });
+            }
+
+            if let (true, Some(start)) = (detect_loops, first_seen[ip as usize]) {
+                return Ok(RunOutcome::InfiniteLoop {
+                    ip,
+                    body: trace[start..].to_vec(),
+                    state: self.state.clone(),
+                });
+            }
+
+            if let Some(fuel) = fuel.as_mut() {
+                if *fuel == 0 {
+                    return Ok(RunOutcome::OutOfFuel(self.state.clone()));
+                }
+                *fuel -= 1;
+            }
+
+            if detect_loops {
+                first_seen[ip as usize] = Some(trace.len());
+                trace.push(ip);
+            }
+            self.step()?;
+        }
+    }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repair {
+    pub ip: i32,
+    pub original: Instruction,
+    pub replacement: Instruction,
+    pub state: State,
+}
+
+// Marks every ip from which the unmodified program runs to completion, by
+// walking backwards from the exit. Index `instructions.len()` is the exit.
+fn reaches_exit(instructions: &[Instruction]) -> Vec<bool> {
+    let len = instructions.len();
+    let mut predecessors = vec![Vec::new(); len + 1];
+    for (ip, inst) in instructions.iter().enumerate() {
+        let next = inst.successor(ip as i32);
+        if next >= 0 && next as usize <= len {
+            predecessors[next as usize].push(ip);
+        }
+    }
+
+    let mut reaches = vec![false; len + 1];
+    let mut stack = vec![len];
+    reaches[len] = true;
+    while let Some(ip) = stack.pop() {
+        for prev in predecessors[ip].iter().copied() {
+            if !reaches[prev] {
+                reaches[prev] = true;
+                stack.push(prev);
+            }
+        }
+    }
+
+    reaches
+}
+
+pub fn repair(instructions: &[Instruction]) -> anyhow::Result<Option<Repair>> {
+    if instructions.iter().any(Instruction::is_conditional) {
+        Err(anyhow::Error::msg("repair requires control flow that does not depend on data"))?;
+    }
+
+    // A program that already terminates needs no repair.
+    if let RunOutcome::Terminated(_) = VM::new(instructions.to_vec(), Default::default()).run()? {
+        return Ok(None);
+    }
+
+    let len = instructions.len() as i32;
+    let reaches = reaches_exit(instructions);
+    let mut visited = vec![false; instructions.len()];
+    let mut ip = 0;
+
+    // Only instructions on the original execution path can change the
+    // outcome, so follow it and take the first flip that lands somewhere
+    // already known to reach the exit.
+    while ip >= 0 && ip < len && !visited[ip as usize] {
+        visited[ip as usize] = true;
+        let original = instructions[ip as usize];
+
+        if let Some(replacement) = original.flipped() {
+            let next = replacement.successor(ip);
+            if next >= 0 && next <= len && reaches[next as usize] {
+                let mut patched = instructions.to_vec();
+                patched[ip as usize] = replacement;
+
+                // A patch that still fails is only a dead end; carry on
+                // along the original path.
+                let mut vm = VM::new(patched, Default::default());
+                if let Ok(RunOutcome::Terminated(state)) = vm.run() {
+                    return Ok(Some(Repair{
+                        ip,
+                        original,
+                        replacement,
+                        state,
+                    }));
+                }
+            }
+        }
+
+        ip = original.successor(ip);
+    }
+
+    Ok(None)
+}
+
+#[test]
+fn test_run() {
+    use Instruction::*;
+
+    let program = vec![
+        Nop(0), Acc(1), Jmp(4), Acc(3), Jmp(-3),
+        Acc(-99), Acc(1), Jmp(-4), Acc(6),
+    ];
+    let mut vm = VM::new(program.clone(), Default::default());
+    assert_eq!(vm.run().unwrap(), RunOutcome::InfiniteLoop {
+        ip: 1,
+        body: vec![1, 2, 6, 7, 3, 4],
+        state: State{ ip: 1, acc: 5, ..Default::default() },
+    });
+
+    let mut fixed = program.clone();
+    fixed[7] = Nop(-4);
+    let mut vm = VM::new(fixed, Default::default());
+    assert_eq!(vm.run().unwrap(), RunOutcome::Terminated(State{ ip: 9, acc: 8, ..Default::default() }));
+
+    let mut vm = VM::new(vec![Jmp(5)], Default::default());
+    assert_eq!(vm.run().unwrap(), RunOutcome::JumpedOutOfRange {
+        ip: 5,
+        state: State{ ip: 5, acc: 0, ..Default::default() },
+    });
+
+    let mut vm = VM::new(program, Default::default());
+    assert_eq!(vm.run_with_fuel(3).unwrap(), RunOutcome::OutOfFuel(State{ ip: 6, acc: 1, ..Default::default() }));
+
+    // A data-dependent loop can't be detected, so it stops for lack of fuel.
+    let mut vm = VM::new(vec![Mov(Register::A, Operand::Imm(1)), Jnz(Operand::Reg(Register::A), 0)], Default::default());
+    assert_eq!(vm.run().unwrap(), RunOutcome::OutOfFuel(State{ ip: 1, regs: [1, 0, 0, 0], ..Default::default() }));
+}
+
+#[test]
+fn test_repair() {
+    use Instruction::*;
+
+    let program = vec![
+        Nop(0), Acc(1), Jmp(4), Acc(3), Jmp(-3),
+        Acc(-99), Acc(1), Jmp(-4), Acc(6),
+    ];
+    assert_eq!(repair(&program).unwrap(), Some(Repair{
+        ip: 7,
+        original: Jmp(-4),
+        replacement: Nop(-4),
+        state: State{ ip: 9, acc: 8, ..Default::default() },
+    }));
+
+    assert_eq!(repair(&[Jmp(0), Acc(1)]).unwrap(), Some(Repair{
+        ip: 0,
+        original: Jmp(0),
+        replacement: Nop(0),
+        state: State{ ip: 2, acc: 1, ..Default::default() },
+    }));
+
+    assert_eq!(repair(&[Nop(0), Acc(1), Jmp(-2), Jmp(-3)]).unwrap(), None);
+
+    // Already terminates, so there is nothing to flip.
+    assert_eq!(repair(&[Nop(0)]).unwrap(), None);
+
+    // Flipping the nop at 0 reaches the exit but overflows on the way, so
+    // the search moves on to the jmp at 2.
+    assert_eq!(repair(&[Nop(3), Acc(-1), Jmp(-2), Acc(i32::MAX), Acc(1)]).unwrap(), Some(Repair{
+        ip: 2,
+        original: Jmp(-2),
+        replacement: Nop(-2),
+        state: State{ ip: 5, acc: i32::MAX as i128, ..Default::default() },
+    }));
+}
+
+#[test]
+fn test_history() {
+    use Instruction::*;
+
+    let mut vm = VM::new(vec![Acc(2), Nop(0), Acc(3), Jmp(1), Nop(0)], Default::default());
+    assert!(vm.step_back().is_err());
+
+    vm.set_recording(true);
+    for _ in 0..4 {
+        vm.step().unwrap();
+    }
+    assert_eq!(vm.state(), &State{ ip: 4, acc: 5, ..Default::default() });
+    assert_eq!(vm.last_acc_change(), Some((2, 2)));
+
+    vm.step_back().unwrap();
+    vm.step_back().unwrap();
+    assert_eq!(vm.state(), &State{ ip: 2, acc: 2, ..Default::default() });
+    assert_eq!(vm.last_acc_change(), Some((0, 0)));
+
+    vm.goto_step(4).unwrap();
+    assert_eq!(vm.state(), &State{ ip: 4, acc: 5, ..Default::default() });
+    vm.goto_step(1).unwrap();
+    assert_eq!(vm.state(), &State{ ip: 1, acc: 2, ..Default::default() });
+    assert_eq!(vm.step_count(), 1);
+}
+
+#[test]
+fn test_extended() {
+    use Instruction::*;
+    use Register::{A, B, C, D};
+
+    // Sums the inputs on port 0 into memory[1] until a zero is read, then
+    // outputs the sum and how many values were odd.
+    let program = vec![
+        In(A, 0),
+        Jz(Operand::Reg(A), 7),
+        Load(B, Operand::Imm(1)),
+        Add(B, Operand::Reg(A)),
+        Store(Operand::Imm(1), Operand::Reg(B)),
+        Mod(A, Operand::Imm(2)),
+        Add(C, Operand::Reg(A)),
+        Jmp(-7),
+        Load(D, Operand::Imm(1)),
+        Out(1, Operand::Reg(D)),
+        Out(1, Operand::Reg(C)),
+        Jlt(Operand::Reg(D), Operand::Imm(100), 2),
+        Mul(Register::Acc, Operand::Imm(0)),
+        Mov(Register::Acc, Operand::Imm(7)),
+    ];
+
+    let mut vm = VM::new(program.clone(), Default::default());
+    for v in [3, 4, 5].iter().copied() {
+        vm.push_input(0, v);
+    }
+    let err = vm.run().unwrap_err();
+    assert_eq!(err.downcast_ref::<WouldBlock>(), Some(&WouldBlock{ port: 0 }));
+
+    vm.push_input(0, 0);
+    vm.set_recording(true);
+    assert!(vm.run_with_fuel(100).unwrap().is_terminated());
+    assert_eq!(vm.output(1), &[12, 2]);
+    assert_eq!(vm.memory()[1], 12);
+    assert_eq!(vm.state().acc, 7);
+
+    vm.goto_step(0).unwrap();
+    assert_eq!(vm.state().ip, 0);
+    assert_eq!(vm.memory()[1], 12);
+    assert!(vm.output(1).is_empty());
+
+    assert!(vm.run_with_fuel(100).unwrap().is_terminated());
+    assert_eq!(vm.take_output(1), vec![12, 2]);
+    assert!(vm.step_back().is_err());
+    assert!(vm.output(1).is_empty());
+
+    let mut vm = VM::new(vec![Mod(A, Operand::Reg(B))], Default::default());
+    assert!(vm.step().is_err());
+    assert_eq!(vm.state().ip, 0);
+}
+
+#[test]
+fn test_arithmetic() {
+    use Instruction::*;
+
+    let program = vec![Acc(i32::MAX), Acc(1), Mul(Register::Acc, Operand::Imm(-4))];
+    let run = |width, overflow| {
+        let mut vm = VM::new(program.clone(), Default::default());
+        vm.set_arithmetic(Arithmetic{ width, overflow }).unwrap();
+        vm.run().map(|outcome| outcome.state().acc)
+    };
+
+    let err = run(Width::I32, Overflow::Checked).unwrap_err();
+    assert_eq!(err.downcast_ref::<OverflowError>(), Some(&OverflowError{ register: Register::Acc, width: Width::I32 }));
+    assert_eq!(run(Width::I32, Overflow::Wrapping).unwrap(), 0);
+    assert_eq!(run(Width::I32, Overflow::Saturating).unwrap(), i32::MIN as i128);
+    assert_eq!(run(Width::I64, Overflow::Checked).unwrap(), -(1i128 << 33));
+
+    // A failed step leaves the state untouched.
+    let mut vm = VM::new(program.clone(), Default::default());
+    vm.step().unwrap();
+    assert!(vm.step().is_err());
+    assert_eq!(vm.state(), &State{ ip: 1, acc: i32::MAX as i128, ..Default::default() });
+
+    // Narrowing acc into a 32-bit register follows the same policy.
+    let program = vec![Mul(Register::Acc, Operand::Imm(0)), Add(Register::Acc, Operand::Imm(i32::MIN)),
+        Add(Register::Acc, Operand::Imm(-1)), Mov(Register::A, Operand::Reg(Register::Acc))];
+    let mut vm = VM::new(program, Default::default());
+    vm.set_arithmetic(Arithmetic{ width: Width::I64, overflow: Overflow::Saturating }).unwrap();
+    vm.run().unwrap();
+    assert_eq!(vm.state().regs[0], i32::MIN);
+    assert!(vm.set_arithmetic(Arithmetic{ width: Width::I32, overflow: Overflow::Checked }).is_err());
+
+    let mut vm = VM::new(vec![Mul(Register::Acc, Operand::Imm(-1)), Mul(Register::Acc, Operand::Reg(Register::Acc))], Default::default());
+    vm.set_arithmetic(Arithmetic{ width: Width::I128, overflow: Overflow::Saturating }).unwrap();
+    vm.state.acc = i128::MAX;
+    vm.run().unwrap();
+    assert_eq!(vm.state().acc, i128::MAX);
+}